@@ -0,0 +1,554 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+};
+
+use fst::{Automaton, IntoStreamer, Map, MapBuilder, Streamer};
+use memmap2::Mmap;
+
+use crate::{
+    correction::{EditState, PrefixEdits},
+    models::{
+        bigrams::{BigramModel, ContextCounts, WordCount},
+        layouts::LayoutModel,
+    },
+    repositories::{Context, StoreError},
+    utils::{get_key_group, saturating_u32},
+};
+
+// Key layout, with `context` and `word` separated by `SEPARATOR`:
+//   EXACT context word      -> count
+//   EXTENDING suffix word   -> count << 32 | contexts
+//   TOTAL family context    -> total
+//   TYPES family context    -> types << 32 | total_contexts
+//   FORM word form          -> 0, for the most frequent surface form of the word
+//   LAYOUT name layout      -> 0, with the layout as JSON
+const EXACT: u8 = b'c';
+const EXTENDING: u8 = b'e';
+const TOTAL: u8 = b't';
+const TYPES: u8 = b'n';
+const FORM: u8 = b'f';
+const LAYOUT: u8 = b'l';
+const SEPARATOR: u8 = 0x1f;
+
+fn pack(high: u64, low: u64) -> u64 {
+    ((saturating_u32(high) as u64) << 32) | saturating_u32(low) as u64
+}
+
+fn unpack(value: u64) -> (u64, u64) {
+    (value >> 32, value & u32::MAX as u64)
+}
+
+fn key(parts: &[&[u8]]) -> Vec<u8> {
+    parts.concat()
+}
+
+/// Number of bytes of the UTF-8 character that starts with `byte`.
+fn utf8_len(byte: u8) -> usize {
+    match byte {
+        0x00..=0x7f => 1,
+        0xf0..=0xff => 4,
+        0xe0..=0xef => 3,
+        _ => 2,
+    }
+}
+
+/// Splits `context` into its key family and the context keys of that family start with.
+fn family(context: Context<'_>) -> (u8, &str) {
+    match context {
+        Context::Exact(context) => (EXACT, context),
+        Context::Extending(suffix) => (EXTENDING, suffix),
+    }
+}
+
+/// Immutable, memory-mapped FST holding everything `find_counts`, `find_forms` and
+/// `find_layout` need, so read replicas can serve predictions without a database.
+pub struct ModelArtifact {
+    map: Map<Mmap>,
+}
+
+impl ModelArtifact {
+    /// Compiles `ngrams`, the surface `forms` of their words and the named `layouts` into an
+    /// artifact at `path`, returning its number of keys. The file is written next to `path`
+    /// first and then renamed, so readers never see a partial artifact.
+    pub fn build(
+        ngrams: Vec<BigramModel>,
+        forms: HashMap<String, String>,
+        layouts: &[LayoutModel],
+        path: &Path,
+    ) -> Result<usize, StoreError> {
+        let mut extensions = HashMap::<(String, String), (u64, u64)>::new();
+        let mut stats = HashMap::<(u8, String), (u64, u64, u64)>::new();
+        let mut entries = BTreeMap::<Vec<u8>, u64>::new();
+
+        for ngram in &ngrams {
+            let count = ngram.count as u64;
+            entries.insert(
+                key(&[
+                    &[EXACT],
+                    ngram.first.as_bytes(),
+                    &[SEPARATOR],
+                    ngram.second.as_bytes(),
+                ]),
+                count,
+            );
+
+            let exact = stats.entry((EXACT, ngram.first.clone())).or_default();
+            *exact = (exact.0 + count, exact.1 + 1, exact.2 + 1);
+
+            if !ngram.first.is_empty() {
+                let suffix = ngram.first.split_once(' ').map_or("", |(_, suffix)| suffix);
+                let extension = extensions
+                    .entry((suffix.to_string(), ngram.second.clone()))
+                    .or_default();
+                if extension.0 == 0 {
+                    let extending = stats.entry((EXTENDING, suffix.to_string())).or_default();
+                    extending.2 += 1;
+                }
+                *extension = (extension.0 + count, extension.1 + 1);
+
+                let extending = stats.entry((EXTENDING, suffix.to_string())).or_default();
+                *extending = (extending.0 + count, extending.1 + 1, extending.2);
+            }
+        }
+
+        for ((suffix, word), (count, contexts)) in extensions {
+            let key = key(&[
+                &[EXTENDING],
+                suffix.as_bytes(),
+                &[SEPARATOR],
+                word.as_bytes(),
+            ]);
+            entries.insert(key, pack(count, contexts));
+        }
+        for ((family, context), (total, total_contexts, types)) in stats {
+            entries.insert(key(&[&[TOTAL, family], context.as_bytes()]), total);
+            let value = pack(types, total_contexts);
+            entries.insert(key(&[&[TYPES, family], context.as_bytes()]), value);
+        }
+        for (word, form) in forms {
+            let key = key(&[&[FORM], word.as_bytes(), &[SEPARATOR], form.as_bytes()]);
+            entries.insert(key, 0);
+        }
+        for layout in layouts {
+            let Some(name) = &layout.name else {
+                continue;
+            };
+            let json = serde_json::to_vec(&LayoutModel {
+                id: None,
+                ..layout.clone()
+            })?;
+            entries.insert(key(&[&[LAYOUT], name.as_bytes(), &[SEPARATOR], &json]), 0);
+        }
+
+        let partial = path.with_extension("partial");
+        let mut builder = MapBuilder::new(BufWriter::new(File::create(&partial)?))?;
+        builder.extend_iter(entries.iter().map(|(key, value)| (key, *value)))?;
+        builder.finish()?;
+        std::fs::rename(&partial, path)?;
+
+        Ok(entries.len())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, StoreError> {
+        let file = File::open(path)?;
+        // Safety: artifacts are only ever replaced by renaming a new file over them, never
+        // modified in place, so the mapped bytes cannot change under us.
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self {
+            map: Map::new(mmap)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> ContextCounts {
+        let (family, context) = family(context);
+
+        let total = self
+            .map
+            .get(key(&[&[TOTAL, family], context.as_bytes()]))
+            .unwrap_or(0);
+        let (types, total_contexts) = self
+            .map
+            .get(key(&[&[TYPES, family], context.as_bytes()]))
+            .map_or((0, 0), unpack);
+
+        let prefix = key(&[&[family], context.as_bytes(), &[SEPARATOR]]);
+        let typed = second
+            .unwrap_or_default()
+            .chars()
+            .map(|letter| (letter, get_key_group(letter, groups)))
+            .collect::<Vec<(char, Option<String>)>>();
+        let automaton = LayoutPrefix {
+            prefix: &prefix,
+            groups: &typed,
+        };
+
+        let mut words = vec![];
+        let mut stream = self.map.search(automaton).into_stream();
+        while let Some((key, value)) = stream.next() {
+            let word = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+            let (count, contexts) = match family {
+                EXACT => (value, 1),
+                _ => unpack(value),
+            };
+            words.push(WordCount {
+                word,
+                count,
+                contexts,
+            });
+        }
+
+        ContextCounts {
+            total,
+            total_contexts,
+            types,
+            words,
+        }
+    }
+
+    /// Words following `context` that `edits` finds to be corrections, with their edits.
+    pub fn find_similar(
+        &self,
+        context: Context<'_>,
+        edits: &PrefixEdits<'_>,
+    ) -> Vec<(WordCount, f64)> {
+        let (family, context) = family(context);
+        let prefix = key(&[&[family], context.as_bytes(), &[SEPARATOR]]);
+        let automaton = Similar {
+            prefix: &prefix,
+            edits,
+        };
+
+        let mut similar = vec![];
+        let mut stream = self.map.search_with_state(automaton).into_stream();
+        while let Some((key, value, state)) = stream.next() {
+            let SimilarState::Word { edits, .. } = state else {
+                continue;
+            };
+            let word = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+            let (count, contexts) = match family {
+                EXACT => (value, 1),
+                _ => unpack(value),
+            };
+            let found = WordCount {
+                word,
+                count,
+                contexts,
+            };
+            similar.push((found, edits.distance));
+        }
+        similar
+    }
+
+    /// The most frequent surface form of each of `words` that had been seen when it was built.
+    pub fn find_forms(&self, words: &[&str]) -> HashMap<String, String> {
+        let mut forms = HashMap::new();
+        for word in words {
+            let prefix = key(&[&[FORM], word.as_bytes(), &[SEPARATOR]]);
+            let end = key(&[&[FORM], word.as_bytes(), &[SEPARATOR + 1]]);
+            let mut stream = self.map.range().ge(&prefix).lt(&end).into_stream();
+            if let Some((key, _)) = stream.next() {
+                let form = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+                forms.insert(word.to_string(), form);
+            }
+        }
+        forms
+    }
+
+    /// The layout named `name` as it was when the artifact was built.
+    pub fn find_layout(&self, name: &str) -> Option<LayoutModel> {
+        let prefix = key(&[&[LAYOUT], name.as_bytes(), &[SEPARATOR]]);
+        let end = key(&[&[LAYOUT], name.as_bytes(), &[SEPARATOR + 1]]);
+        let mut stream = self.map.range().ge(&prefix).lt(&end).into_stream();
+        let (key, _) = stream.next()?;
+        serde_json::from_slice(&key[prefix.len()..]).ok()
+    }
+}
+
+/// Matches the keys that start with `prefix` and continue with a word whose letters fit the
+/// typed key `groups`, decoding the word's UTF-8 one character at a time.
+struct LayoutPrefix<'a> {
+    prefix: &'a [u8],
+    groups: &'a [(char, Option<String>)],
+}
+
+#[derive(Clone)]
+enum PrefixState {
+    Key(usize),
+    Letter {
+        index: usize,
+        bytes: [u8; 4],
+        len: usize,
+    },
+    Matched,
+    Dead,
+}
+
+impl LayoutPrefix<'_> {
+    fn after_prefix(&self) -> PrefixState {
+        if self.groups.is_empty() {
+            PrefixState::Matched
+        } else {
+            PrefixState::Letter {
+                index: 0,
+                bytes: [0; 4],
+                len: 0,
+            }
+        }
+    }
+}
+
+impl Automaton for LayoutPrefix<'_> {
+    type State = PrefixState;
+
+    fn start(&self) -> PrefixState {
+        if self.prefix.is_empty() {
+            self.after_prefix()
+        } else {
+            PrefixState::Key(0)
+        }
+    }
+
+    fn is_match(&self, state: &PrefixState) -> bool {
+        matches!(state, PrefixState::Matched)
+    }
+
+    fn can_match(&self, state: &PrefixState) -> bool {
+        !matches!(state, PrefixState::Dead)
+    }
+
+    fn will_always_match(&self, state: &PrefixState) -> bool {
+        matches!(state, PrefixState::Matched)
+    }
+
+    fn accept(&self, state: &PrefixState, byte: u8) -> PrefixState {
+        match state {
+            PrefixState::Key(i) if self.prefix[*i] == byte => {
+                if i + 1 == self.prefix.len() {
+                    self.after_prefix()
+                } else {
+                    PrefixState::Key(i + 1)
+                }
+            }
+            PrefixState::Key(_) | PrefixState::Dead => PrefixState::Dead,
+            PrefixState::Matched => PrefixState::Matched,
+            PrefixState::Letter { index, bytes, len } => {
+                let mut bytes = *bytes;
+                bytes[*len] = byte;
+                let len = len + 1;
+
+                if len < utf8_len(bytes[0]) {
+                    return PrefixState::Letter {
+                        index: *index,
+                        bytes,
+                        len,
+                    };
+                }
+
+                let c = match std::str::from_utf8(&bytes[..len]) {
+                    Ok(c) => c.chars().next().unwrap(),
+                    Err(_) => return PrefixState::Dead,
+                };
+                let (letter, group) = &self.groups[*index];
+                let allowed = match group {
+                    Some(chars) => chars.contains(c),
+                    None => c == *letter,
+                };
+
+                match (allowed, index + 1 == self.groups.len()) {
+                    (false, _) => PrefixState::Dead,
+                    (true, true) => PrefixState::Matched,
+                    (true, false) => PrefixState::Letter {
+                        index: index + 1,
+                        bytes: [0; 4],
+                        len: 0,
+                    },
+                }
+            }
+        }
+    }
+}
+
+/// Matches the keys that start with `prefix` and continue with a word `edits` finds to be a
+/// correction, decoding the word's UTF-8 one character at a time.
+struct Similar<'a, 'b> {
+    prefix: &'a [u8],
+    edits: &'a PrefixEdits<'b>,
+}
+
+#[derive(Clone)]
+enum SimilarState {
+    Key(usize),
+    Word {
+        edits: EditState,
+        bytes: [u8; 4],
+        len: usize,
+    },
+    Dead,
+}
+
+impl Similar<'_, '_> {
+    fn after_prefix(&self) -> SimilarState {
+        SimilarState::Word {
+            edits: self.edits.start(),
+            bytes: [0; 4],
+            len: 0,
+        }
+    }
+}
+
+impl Automaton for Similar<'_, '_> {
+    type State = SimilarState;
+
+    fn start(&self) -> SimilarState {
+        if self.prefix.is_empty() {
+            self.after_prefix()
+        } else {
+            SimilarState::Key(0)
+        }
+    }
+
+    fn is_match(&self, state: &SimilarState) -> bool {
+        match state {
+            SimilarState::Word { edits, len: 0, .. } => self.edits.is_correction(edits),
+            _ => false,
+        }
+    }
+
+    fn can_match(&self, state: &SimilarState) -> bool {
+        match state {
+            SimilarState::Key(_) => true,
+            SimilarState::Word { edits, .. } => self.edits.can_match(edits),
+            SimilarState::Dead => false,
+        }
+    }
+
+    fn accept(&self, state: &SimilarState, byte: u8) -> SimilarState {
+        match state {
+            SimilarState::Key(i) if self.prefix[*i] == byte => {
+                if i + 1 == self.prefix.len() {
+                    self.after_prefix()
+                } else {
+                    SimilarState::Key(i + 1)
+                }
+            }
+            SimilarState::Key(_) | SimilarState::Dead => SimilarState::Dead,
+            SimilarState::Word { edits, bytes, len } => {
+                let mut bytes = *bytes;
+                bytes[*len] = byte;
+                let len = len + 1;
+                if len < utf8_len(bytes[0]) {
+                    return SimilarState::Word {
+                        edits: edits.clone(),
+                        bytes,
+                        len,
+                    };
+                }
+
+                let c = match std::str::from_utf8(&bytes[..len]) {
+                    Ok(c) => c.chars().next().unwrap(),
+                    Err(_) => return SimilarState::Dead,
+                };
+                // Once longer words cannot get nearer, the edits so far hold for all of them.
+                let edits = if self.edits.can_improve(edits) {
+                    self.edits.step(edits, c)
+                } else {
+                    edits.clone()
+                };
+                SimilarState::Word {
+                    edits,
+                    bytes: [0; 4],
+                    len: 0,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{index::PrefixIndex, models::layouts::fixtures::qwerty, utils::legacy_groups};
+
+    #[test]
+    fn test_artifact_matches_prefix_index() {
+        let groups = legacy_groups(&["qwertyuiop", "asdfghjkl;", "zxcvbnm,./"].map(String::from));
+
+        let words = "el niño y la niña cantan la canción del niño"
+            .split(' ')
+            .collect::<Vec<&str>>();
+        let mut index = PrefixIndex::default();
+        for i in 0..words.len() {
+            for n in 1..=3.min(i + 1) {
+                index.insert(&words[i + 1 - n..i].join(" "), words[i], 1);
+            }
+        }
+
+        let forms = HashMap::from([("nino".to_string(), "niño".to_string())]);
+        let path = std::env::temp_dir().join(format!("artifact-{}.fst", std::process::id()));
+        ModelArtifact::build(index.ngrams(), forms.clone(), &[qwerty()], &path).unwrap();
+        let artifact = ModelArtifact::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(artifact.find_forms(&["nino", "nin", "la"]), forms);
+        let layout = artifact.find_layout("qwerty").unwrap();
+        assert_eq!(layout.keys, qwerty().keys);
+        assert!(artifact.find_layout("qwert").is_none());
+
+        for context in [
+            Context::Exact(""),
+            Context::Exact("la"),
+            Context::Exact("del"),
+            Context::Extending(""),
+            Context::Extending("la"),
+        ] {
+            for second in [None, Some("n"), Some("ni"), Some("cañ"), Some("x")] {
+                let expected = index.find_counts(context, second, &groups);
+                let found = artifact.find_counts(context, second, &groups);
+
+                let words = |counts: &ContextCounts| {
+                    let mut words = counts
+                        .words
+                        .iter()
+                        .map(|word| (word.word.clone(), word.count, word.contexts))
+                        .collect::<Vec<(String, u64, u64)>>();
+                    words.sort();
+                    words
+                };
+                assert_eq!(words(&found), words(&expected), "{context:?} {second:?}");
+                assert_eq!(
+                    (found.total, found.total_contexts, found.types),
+                    (expected.total, expected.total_contexts, expected.types),
+                    "{context:?}"
+                );
+            }
+
+            let edits = PrefixEdits::new("nilo", 1.0, &|_, _| 1.0);
+            let similar = |similar: Vec<(WordCount, f64)>| {
+                let mut similar = similar
+                    .into_iter()
+                    .map(|(word, distance)| (word.word, word.count, distance))
+                    .collect::<Vec<(String, u64, f64)>>();
+                similar.sort_by(|a, b| a.0.cmp(&b.0));
+                similar
+            };
+            assert_eq!(
+                similar(artifact.find_similar(context, &edits)),
+                similar(index.find_similar(context, &edits)),
+                "{context:?}"
+            );
+        }
+    }
+}
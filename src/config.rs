@@ -0,0 +1,124 @@
+use crate::{
+    language_id::LanguageIdentifier, normalization::Normalizer, smoothing::Smoothing,
+    tokenizer::Tokenizer,
+};
+
+/// A language whose text is counted and predicted separately from the others.
+#[derive(Debug, Clone)]
+pub struct Language {
+    /// Short code requests select the language by, such as "en" or "pt-BR".
+    pub code: String,
+    /// Form words of this language are counted in and looked up in.
+    pub normalizer: Normalizer,
+}
+
+/// Deployment settings read from the environment at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Longest n-gram counted by `process_text` and used as context by `predict`.
+    pub ngram_order: usize,
+    /// Default probability estimation of `predict`.
+    pub smoothing: Smoothing,
+    /// How ingested and typed text is split into words.
+    pub tokenizer: Tokenizer,
+    /// Languages with their own model. The first one serves requests that name none.
+    pub languages: Vec<Language>,
+    /// Picks the language of requests that name none.
+    pub identifier: LanguageIdentifier,
+    /// Name of this server among those sharing a database, which owns the jobs it runs.
+    pub instance: String,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        let ngram_order = std::env::var("NGRAM_ORDER")
+            .map(|order| {
+                order
+                    .parse::<usize>()
+                    .expect("NGRAM_ORDER must be a number")
+            })
+            .unwrap_or(default.ngram_order);
+        if ngram_order < 2 {
+            panic!("NGRAM_ORDER must be at least 2");
+        }
+
+        let languages = std::env::var("LANGUAGES").unwrap_or(default.languages[0].code.clone());
+        let languages = languages
+            .split(',')
+            .map(str::trim)
+            .map(|code| {
+                let valid = code.split('-').all(|part| {
+                    !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric())
+                });
+                if !valid {
+                    panic!("LANGUAGES must be a comma separated list of codes such as en,es");
+                }
+                Language {
+                    code: code.to_string(),
+                    normalizer: Normalizer::from_env(code),
+                }
+            })
+            .collect::<Vec<Language>>();
+        let codes = languages
+            .iter()
+            .map(|language| language.code.as_str())
+            .collect::<Vec<&str>>();
+        // Codes name the collections and tables of their model, which SQLite does not tell
+        // apart by case.
+        for (i, code) in codes.iter().enumerate() {
+            if codes[..i]
+                .iter()
+                .any(|other| other.eq_ignore_ascii_case(code))
+            {
+                panic!("LANGUAGES lists {code:?} twice");
+            }
+        }
+        let identifier = LanguageIdentifier::from_env(&codes);
+
+        let instance = std::env::var("INSTANCE_ID")
+            .or_else(|_| std::env::var("HOSTNAME"))
+            .unwrap_or(default.instance);
+
+        Self {
+            ngram_order,
+            smoothing: Smoothing::from_env(),
+            tokenizer: Tokenizer::from_env(),
+            languages,
+            identifier,
+            instance,
+        }
+    }
+
+    /// The language with `code`, or the default one when no code is given.
+    pub fn language(&self, code: Option<&str>) -> Option<&Language> {
+        match code {
+            Some(code) => self.languages.iter().find(|language| language.code == code),
+            None => self.languages.first(),
+        }
+    }
+
+    pub fn language_codes(&self) -> Vec<&str> {
+        self.languages
+            .iter()
+            .map(|language| language.code.as_str())
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ngram_order: 3,
+            smoothing: Smoothing::Mle,
+            tokenizer: Tokenizer::default(),
+            languages: vec![Language {
+                code: "en".to_string(),
+                normalizer: Normalizer::default(),
+            }],
+            identifier: LanguageIdentifier::builtin(&["en"]),
+            instance: "default".to_string(),
+        }
+    }
+}
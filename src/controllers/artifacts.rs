@@ -0,0 +1,173 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use serde_json::json;
+
+use super::{bad_request, find_language};
+use crate::{
+    config::Config,
+    models::bigrams::LanguageQuery,
+    repositories::{artifact::NothingToExport, Repo},
+};
+
+pub fn register_routes(cfg: &mut web::ServiceConfig) {
+    let scope = web::scope("artifact");
+    cfg.service(
+        scope
+            .service(get_artifact)
+            .service(build_artifact)
+            .service(reload_artifact),
+    );
+}
+
+fn not_configured() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "error": "MODEL_ARTIFACT is not configured" }))
+}
+
+#[get("")]
+async fn get_artifact(
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    query: web::Query<LanguageQuery>,
+) -> impl Responder {
+    let (_, model) = match find_language(&config, &repo, query.language.as_deref()) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+
+    match &model.artifact {
+        Some(artifact) => HttpResponse::Ok().json(json!({
+            "data": { "loaded": artifact.loaded().is_some(), "keys": artifact.loaded() }
+        })),
+        None => not_configured(),
+    }
+}
+
+#[post("")]
+async fn build_artifact(
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    query: web::Query<LanguageQuery>,
+) -> impl Responder {
+    let (_, model) = match find_language(&config, &repo, query.language.as_deref()) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+    let Some(artifact) = &model.artifact else {
+        return not_configured();
+    };
+
+    let layouts = match repo.layouts.find_all().await {
+        Ok(layouts) => layouts,
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    };
+    match artifact.build(&layouts).await {
+        Ok(keys) => HttpResponse::Created().json(json!({ "data": { "keys": keys } })),
+        Err(err) if err.is::<NothingToExport>() => {
+            HttpResponse::Conflict().json(json!({ "error": err.to_string() }))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
+    }
+}
+
+#[post("/reload")]
+async fn reload_artifact(
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    query: web::Query<LanguageQuery>,
+) -> impl Responder {
+    let (_, model) = match find_language(&config, &repo, query.language.as_deref()) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+    let Some(artifact) = &model.artifact else {
+        return not_configured();
+    };
+
+    match artifact.reload() {
+        Ok(keys) => HttpResponse::Ok().json(json!({ "data": { "keys": keys } })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test;
+
+    use crate::{
+        controllers::fixtures::app_with, ingestion::NgramCounter, models::layouts::fixtures::qwerty,
+    };
+
+    #[actix_web::test]
+    async fn test_build_and_reload() {
+        let path = std::env::temp_dir().join(format!("model-{}.fst", std::process::id()));
+        let config = Config::default();
+        let repo = Repo::memory(&["en"]).with_artifact(path.clone());
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+        counter.add_text("hello café");
+        counter
+            .flush(repo.models[0].bigrams.as_ref())
+            .await
+            .unwrap();
+
+        let app = app_with(repo.clone(), config.clone(), "").await;
+
+        let req = test::TestRequest::get().uri("/artifact").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["loaded"], false, "Nothing built yet");
+
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let req = test::TestRequest::post().uri("/artifact").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "Build artifact");
+
+        // New counts only reach predictions through the next build.
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+        counter.add_text("hello there, cafe cafe");
+        counter
+            .flush(repo.models[0].bigrams.as_ref())
+            .await
+            .unwrap();
+        let counts = repo.models[0]
+            .bigrams
+            .find_counts(crate::repositories::Context::Exact("hello"), None, &[])
+            .await
+            .unwrap();
+        assert_eq!(counts.types, 1);
+        let forms = repo.models[0].bigrams.find_forms(&["cafe"]).await.unwrap();
+        assert_eq!(forms["cafe"], "café");
+
+        let req = test::TestRequest::post()
+            .uri("/artifact/reload")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success(), "Reload artifact");
+
+        // A replica without a database predicts with the layouts of the artifact, and does not
+        // replace the artifact with its empty store.
+        let replica = app_with(
+            Repo::memory(&["en"]).with_artifact(path.clone()),
+            config.clone(),
+            "",
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "hello c", "layout": "qwerty" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&replica, req).await;
+        assert_eq!(resp["data"]["prediction"][0]["word"], "café");
+
+        let req = test::TestRequest::post().uri("/artifact").to_request();
+        let resp = test::call_service(&replica, req).await;
+        assert_eq!(resp.status(), 409);
+        let req = test::TestRequest::get().uri("/artifact").to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&replica, req).await;
+        assert_eq!(resp["data"]["loaded"], true);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,131 +1,799 @@
+use std::collections::{HashMap, HashSet};
+
+use actix_multipart::Multipart;
 use actix_web::{get, post, web, HttpResponse, Responder};
+use futures::TryStreamExt;
 use serde_json::json;
-use unidecode::unidecode;
 
+use super::{bad_request, detect_language, find_language};
 use crate::{
+    config::{Config, Language},
+    correction::{add_corrections, FEW_COMPLETIONS},
+    ingestion::{CorpusDecoder, NgramCounter},
     models::{
-        bigrams::{PredictRequest, Prediction, ProcessTextRequest},
+        bigrams::{
+            KeyProbability, LanguageQuery, PredictRequest, Prediction, ProcessTextRequest,
+            SwipeRequest,
+        },
+        layouts::LayoutModel,
         pagination::Pagination,
     },
-    repositories::MongoRepo,
+    repositories::{LanguageModel, PartialWrite, Repo, StoreError},
+    spatial::{Point, TouchModel},
+    tokenizer::{SENTENCE_END, SENTENCE_START},
+    utils::{capitalize, is_all_caps, row_keys},
 };
 
+/// Distinct n-grams an upload keeps in memory before writing them out.
+const FLUSH_SIZE: usize = 100_000;
+
 pub fn register_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(process_text)
+        .service(upload_corpus)
+        .service(process_ndjson)
         .service(get_process_text)
-        .service(predict);
+        .service(predict)
+        .service(next_key)
+        .service(swipe);
 }
 
 #[post("/process_text")]
 async fn process_text(
     data: web::Json<ProcessTextRequest>,
-    repo: web::Data<MongoRepo>,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
 ) -> impl Responder {
-    let text = data
-        .text
-        .chars()
-        .filter(|c| c.is_alphabetic() || c.is_whitespace())
-        .collect::<String>()
-        .to_lowercase();
-    let words = text.split_whitespace().collect::<Vec<&str>>();
-
-    let mut bigram_count = 0;
-    for pair in words.windows(2) {
-        let first = unidecode(&pair[0].replace('ñ', ".")).replace('.', "ñ");
-        let second = unidecode(&pair[1].replace('ñ', ".")).replace('.', "ñ");
-        let result = repo.bigrams.upsert(&first, &second).await;
-
-        match result {
-            Ok(_) => bigram_count += 1,
-            Err(err) => {
-                return HttpResponse::InternalServerError().json(json!({
-                    "error": err.to_string()
-                }));
+    let code = data.language.as_deref();
+    let (language, model) = match detect_language(&config, &repo, code, &data.text) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+
+    let mut counter = NgramCounter::new(&config, language);
+    counter.add_text(&data.text);
+    let bigram_count = counter.bigram_count;
+    let ngram_count = counter.ngram_count;
+
+    let result = counter.flush(model.bigrams.as_ref()).await;
+
+    match result {
+        Ok(()) => HttpResponse::Ok().json(json!({
+            "data": {
+                "language": language.code,
+                "bigram_count": bigram_count,
+                "ngram_count": ngram_count,
             }
+        })),
+        Err(err) => write_error_response(err),
+    }
+}
+
+/// Counts the `.txt` and `.txt.gz` files of a multipart body as they stream in, each file being
+/// its own document. Counts are written whenever `FLUSH_SIZE` distinct n-grams pile up. When
+/// the upload fails, the error lists the files counted so far and the last one fully written.
+#[post("/process_text/upload")]
+async fn upload_corpus(
+    mut payload: Multipart,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    query: web::Query<LanguageQuery>,
+) -> impl Responder {
+    let default = query.language.as_deref();
+    if let Err(err) = find_language(&config, &repo, default) {
+        return bad_request(err);
+    }
+
+    let mut counters = vec![];
+    let mut files = vec![];
+    let mut flushed = None;
+
+    let counted = count_files(
+        &mut payload,
+        &config,
+        &repo,
+        default,
+        &mut counters,
+        &mut files,
+        &mut flushed,
+    )
+    .await;
+    if let Err(err) = counted {
+        let progress = json!({ "files": files, "last_flushed_file": flushed });
+        return match err {
+            UploadError::Rejected(err) => {
+                HttpResponse::BadRequest().json(json!({ "error": err, "data": progress }))
+            }
+            UploadError::Write(err) => stream_error_response(err, progress),
+        };
+    }
+
+    let (bigram_count, ngram_count) = totals(&counters);
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "files": files,
+            "bigram_count": bigram_count,
+            "ngram_count": ngram_count,
         }
+    }))
+}
+
+/// Why an upload stopped part way through.
+enum UploadError {
+    /// The body is not a corpus that can be counted.
+    Rejected(String),
+    Write(StoreError),
+}
+
+/// Counts and writes every file of `payload`, adding a report of each to `files` once it is
+/// counted. `flushed` keeps the position of the last file whose n-grams were all written.
+async fn count_files<'a>(
+    payload: &mut Multipart,
+    config: &'a Config,
+    repo: &'a Repo,
+    default: Option<&str>,
+    counters: &mut Counters<'a>,
+    files: &mut Vec<serde_json::Value>,
+    flushed: &mut Option<usize>,
+) -> Result<(), UploadError> {
+    let rejected = |err: String| UploadError::Rejected(err);
+
+    while let Some(mut field) = payload
+        .try_next()
+        .await
+        .map_err(|err| rejected(err.to_string()))?
+    {
+        let name = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .unwrap_or_default()
+            .to_string();
+        let mut decoder = CorpusDecoder::for_file(&name).ok_or_else(|| {
+            rejected(format!(
+                "Unsupported file {name:?}, expected .txt or .txt.gz"
+            ))
+        })?;
+
+        // Without a language in the query, a file is identified by its first decoded text.
+        let mut file = None;
+        while let Some(chunk) = field
+            .try_next()
+            .await
+            .map_err(|err| rejected(err.to_string()))?
+        {
+            let text = decoder
+                .decode(&chunk)
+                .map_err(|err| rejected(format!("{name}: {err}")))?;
+            if text.is_empty() {
+                continue;
+            }
+            let (position, _, _) = match file {
+                Some(file) => file,
+                None => *file
+                    .insert(start_file(config, repo, default, &text, counters).map_err(rejected)?),
+            };
+            counters[position].1.feed(&text);
+
+            // The file being counted is only partly written by a flush.
+            if flush_counters(counters, FLUSH_SIZE)
+                .await
+                .map_err(UploadError::Write)?
+            {
+                *flushed = files.len().checked_sub(1);
+            }
+        }
+        let text = decoder
+            .finish()
+            .map_err(|err| rejected(format!("{name}: {err}")))?;
+        let (position, words, bigrams) = match file {
+            Some(file) => file,
+            None => start_file(config, repo, default, &text, counters).map_err(rejected)?,
+        };
+        let (model, counter) = &mut counters[position];
+        counter.feed(&text);
+        counter.end_document();
+
+        files.push(json!({
+            "name": name,
+            "language": model.language,
+            "word_count": counter.word_count - words,
+            "bigram_count": counter.bigram_count - bigrams,
+        }));
     }
 
-    HttpResponse::Ok().json(json!({ "data": { "bigram_count": bigram_count } }))
+    flush_counters(counters, 0)
+        .await
+        .map_err(UploadError::Write)?;
+    *flushed = files.len().checked_sub(1);
+    Ok(())
+}
+
+/// Counts a newline-delimited JSON body of `ProcessTextRequest` records as it streams in. Each
+/// record is its own document, in the language it names, else the one of the query, else the
+/// one it is identified as. Records that fail to parse are reported without stopping. When a
+/// write fails, the error lists the records counted so far and the last one fully written.
+#[post("/process_text/ndjson")]
+async fn process_ndjson(
+    mut payload: web::Payload,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    query: web::Query<LanguageQuery>,
+) -> impl Responder {
+    let default = query.language.as_deref();
+    if let Err(err) = find_language(&config, &repo, default) {
+        return bad_request(err);
+    }
+
+    let mut counters = vec![];
+    let mut records = vec![];
+    let mut buffer = vec![];
+    let mut flushed = None;
+
+    loop {
+        let chunk = match payload.try_next().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => return bad_request(err.to_string()),
+        };
+        buffer.extend_from_slice(&chunk);
+
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line = buffer.drain(..=end).collect::<Vec<u8>>();
+            count_record(&config, &repo, default, &mut counters, &line, &mut records);
+        }
+
+        match flush_counters(&mut counters, FLUSH_SIZE).await {
+            Ok(true) => flushed = records.len().checked_sub(1),
+            Ok(false) => {}
+            Err(err) => return ndjson_error_response(err, records, flushed),
+        }
+    }
+    count_record(
+        &config,
+        &repo,
+        default,
+        &mut counters,
+        &buffer,
+        &mut records,
+    );
+
+    if let Err(err) = flush_counters(&mut counters, 0).await {
+        return ndjson_error_response(err, records, flushed);
+    }
+
+    let (bigram_count, ngram_count) = totals(&counters);
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "records": records,
+            "bigram_count": bigram_count,
+            "ngram_count": ngram_count,
+        }
+    }))
+}
+
+/// Reports a failed NDJSON write along with the `records` counted so far and the position of
+/// the last one whose n-grams were all written before, if any. Later ones may be partly written.
+fn ndjson_error_response(
+    err: StoreError,
+    records: Vec<serde_json::Value>,
+    flushed: Option<usize>,
+) -> HttpResponse {
+    let progress = json!({ "records": records, "last_flushed_record": flushed });
+    stream_error_response(err, progress)
+}
+
+/// Counters of the languages seen so far by a streaming endpoint, one per language.
+type Counters<'a> = Vec<(&'a LanguageModel, NgramCounter)>;
+
+/// Position in `counters` of the counter of the language `code` names, or else the one `text`
+/// is identified as. Counters are created on first use.
+fn language_counter<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    code: Option<&str>,
+    text: &str,
+    counters: &mut Counters<'a>,
+) -> Result<usize, String> {
+    let (language, model) = detect_language(config, repo, code, text)?;
+
+    let position = counters
+        .iter()
+        .position(|(counted, _)| counted.language == model.language);
+    Ok(position.unwrap_or_else(|| {
+        counters.push((model, NgramCounter::new(config, language)));
+        counters.len() - 1
+    }))
+}
+
+/// Picks the counter of an uploaded file from its first text, returning its position along
+/// with the counter's word and bigram counts before the file.
+fn start_file<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    code: Option<&str>,
+    text: &[u8],
+    counters: &mut Counters<'a>,
+) -> Result<(usize, usize, usize), String> {
+    let text = String::from_utf8_lossy(text);
+    let position = language_counter(config, repo, code, &text, counters)?;
+    let counter = &counters[position].1;
+    Ok((position, counter.word_count, counter.bigram_count))
+}
+
+/// Counts one NDJSON record with the counter of its language.
+fn count_record<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    default: Option<&str>,
+    counters: &mut Counters<'a>,
+    line: &[u8],
+    records: &mut Vec<serde_json::Value>,
+) {
+    if line.iter().all(u8::is_ascii_whitespace) {
+        return;
+    }
+
+    let record = records.len();
+    let data = match serde_json::from_slice::<ProcessTextRequest>(line) {
+        Ok(data) => data,
+        Err(err) => return records.push(json!({ "record": record, "error": err.to_string() })),
+    };
+    let code = data.language.as_deref().or(default);
+    let position = match language_counter(config, repo, code, &data.text, counters) {
+        Ok(position) => position,
+        Err(err) => return records.push(json!({ "record": record, "error": err })),
+    };
+    let (model, counter) = &mut counters[position];
+
+    let bigrams = counter.bigram_count;
+    counter.add_text(&data.text);
+    records.push(json!({
+        "record": record,
+        "language": model.language,
+        "bigram_count": counter.bigram_count - bigrams,
+    }));
+}
+
+/// Writes out every counter once one of them holds at least `size` distinct n-grams, so that
+/// everything counted before a flush is written after it. Returns whether it flushed.
+async fn flush_counters(counters: &mut Counters<'_>, size: usize) -> Result<bool, StoreError> {
+    if !counters
+        .iter()
+        .any(|(_, counter)| counter.pending_ngrams() >= size)
+    {
+        return Ok(false);
+    }
+    for (model, counter) in counters {
+        counter.flush(model.bigrams.as_ref()).await?;
+    }
+    Ok(true)
+}
+
+/// Bigrams and n-grams counted over every language.
+fn totals(counters: &Counters<'_>) -> (usize, usize) {
+    counters
+        .iter()
+        .fold((0, 0), |(bigrams, ngrams), (_, counter)| {
+            (bigrams + counter.bigram_count, ngrams + counter.ngram_count)
+        })
+}
+
+/// Reports how much of a bulk write made it when only part of it failed.
+fn write_error_response(err: StoreError) -> HttpResponse {
+    stream_error_response(err, serde_json::Value::Null)
+}
+
+/// Like `write_error_response`, adding the `progress` a streaming endpoint had made when its
+/// write failed to the data of the error.
+fn stream_error_response(err: StoreError, progress: serde_json::Value) -> HttpResponse {
+    let mut data = progress;
+    if let Some(partial) = err.downcast_ref::<PartialWrite>() {
+        data["partial"] = json!(true);
+        data["ngrams_written"] = json!(partial.total - partial.failed.len());
+        data["ngrams_failed"] = json!(partial.failed.len());
+    }
+
+    let body = match data {
+        serde_json::Value::Null => json!({ "error": err.to_string() }),
+        data => json!({ "error": err.to_string(), "data": data }),
+    };
+    HttpResponse::InternalServerError().json(body)
+}
+
+/// Text of a `PredictRequest` the way its words are looked up.
+struct Typed {
+    /// Normalized last words of the sentence so far, as many as the n-gram order uses.
+    context: Vec<String>,
+    /// Normalized word being typed, or the letters a keypad key sequence is matched as.
+    last_word: Option<String>,
+    /// Ambiguity groups `last_word` is matched with.
+    groups: Vec<String>,
+    /// Keypad key sequence `last_word` was spelled from.
+    sequence: Option<String>,
+    /// Whether suggestions should be upper-cased, or else capitalized.
+    upper: bool,
+    capital: bool,
+}
+
+fn read_typed(
+    config: &Config,
+    language: &Language,
+    layout: &LayoutModel,
+    touch: Option<&TouchModel>,
+    text: &str,
+) -> Result<Typed, String> {
+    // Keypad key sequences are not words the tokenizer could tell apart from numbers.
+    let (text, sequence) = layout.split_sequence(text);
+
+    let (words, last_word) = config.tokenizer.split_typed(&text);
+    // The word being typed sets the case of suggestions, otherwise the previous word and
+    // whether a sentence is starting do.
+    let upper = match last_word {
+        Some(typed) => is_all_caps(typed),
+        None => words.last().is_some_and(|word| is_all_caps(word)),
+    };
+    let capital = match last_word {
+        Some(typed) => typed.chars().next().is_some_and(char::is_uppercase),
+        None => words == [SENTENCE_START],
+    };
+
+    let normalize = |word: &str| language.normalizer.normalize(word);
+    let words = words.into_iter().map(normalize).collect::<Vec<String>>();
+    let (last_word, groups) = match &sequence {
+        Some(sequence) => {
+            let (spelled, groups) = layout.spell(sequence)?;
+            (Some(spelled), groups)
+        }
+        None => {
+            let last_word = last_word.map(normalize);
+            let groups = match (touch, &last_word) {
+                (Some(touch), Some(typed)) => touch.near_groups(typed),
+                _ => layout.key_groups(),
+            };
+            (last_word, groups)
+        }
+    };
+
+    let context = words[words.len().saturating_sub(config.ngram_order - 1)..].to_vec();
+    Ok(Typed {
+        context,
+        last_word,
+        groups,
+        sequence,
+        upper,
+        capital,
+    })
+}
+
+/// What predicting from `text` typed on the layout named `layout` starts from: the language of
+/// the text, the layout and its touch model, and the text as it is looked up. Fails with the
+/// response to send instead.
+async fn read_request<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    code: Option<&str>,
+    layout: &str,
+    text: &str,
+) -> Result<
+    (
+        &'a Language,
+        &'a LanguageModel,
+        LayoutModel,
+        Option<TouchModel>,
+        Typed,
+    ),
+    HttpResponse,
+> {
+    let (language, model) = detect_language(config, repo, code, text).map_err(bad_request)?;
+
+    // Replicas serving an artifact find the layouts it was built with there.
+    let built = model
+        .artifact
+        .as_ref()
+        .and_then(|artifact| artifact.find_layout(layout));
+    let layout = match built {
+        Some(layout) => Ok(Some(layout)),
+        None => repo.layouts.find(layout).await,
+    };
+    let layout = match layout {
+        Ok(Some(layout)) => layout,
+        Ok(None) => {
+            return Err(HttpResponse::NotFound().json(json!({ "error": "Layout not found" })))
+        }
+        Err(err) => {
+            return Err(
+                HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+            )
+        }
+    };
+    let touch = layout.touch_model();
+    let typed = read_typed(config, language, &layout, touch.as_ref(), text).map_err(bad_request)?;
+    Ok((language, model, layout, touch, typed))
 }
 
 #[post("/predict")]
 async fn predict(
-    repo: web::Data<MongoRepo>,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
     data: web::Json<PredictRequest>,
     query: web::Query<Pagination>,
 ) -> impl Responder {
-    let text = data
-        .text
-        .chars()
-        .filter(|c| c.is_alphabetic() || c.is_whitespace())
-        .collect::<String>()
-        .to_lowercase();
+    let code = data.language.as_deref();
+    let (language, model, layout, touch, typed) =
+        match read_request(&config, &repo, code, &data.layout, &data.text).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+    let Typed {
+        context: words,
+        last_word,
+        groups,
+        sequence,
+        upper,
+        capital,
+    } = typed;
+    let context = words.iter().map(String::as_str).collect::<Vec<&str>>();
 
-    let text = unidecode(&text.replace('ñ', ".")).replace('.', "ñ");
-
-    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let smoothing = data.smoothing.as_ref().unwrap_or(&config.smoothing);
+    let result = model
+        .bigrams
+        .find_predictions(&context, last_word.as_deref(), &groups, smoothing)
+        .await;
 
-    let last_char = text.chars().last();
-    let mut last_word = words.last().cloned();
-    let mut second_to_last_word = words.get(words.len().wrapping_sub(2)).cloned();
+    let mut data = match result {
+        Ok(data) => data,
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    };
+    // Like on phones, words the sequence spells in full come before longer ones.
+    if let Some(sequence) = &sequence {
+        let length = row_keys(sequence).len();
+        data.sort_by_key(|prediction| prediction.word.chars().count() != length);
+    }
+    // Near misses are candidates too, as likely as the keys typed are near theirs.
+    if let (Some(touch), Some(typed)) = (&touch, &last_word) {
+        data = touch.rescore(data, typed);
+    }
+    // Where few words start with the letters typed, words a slip or two away are suggested too.
+    if let (None, Some(typed), true) = (sequence, &last_word, data.len() < FEW_COMPLETIONS) {
+        let store = model.bigrams.as_ref();
+        let adjacency = layout.adjacency();
+        let result = add_corrections(store, &context, typed, &groups, &adjacency, data).await;
+        data = match result {
+            Ok(data) => data,
+            Err(err) => {
+                return HttpResponse::InternalServerError()
+                    .json(json!({ "error": err.to_string() }))
+            }
+        };
+    }
+    let data = paginate(data, &query);
 
-    if Some(' ') == last_char {
-        second_to_last_word = last_word;
-        last_word = None;
+    match present(model, data, upper, capital).await {
+        Ok(data) => HttpResponse::Ok().json(json!({
+            "data": { "language": language.code, "prediction": data }
+        })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
     }
+}
 
-    let layout = repo.layouts.find(&data.layout).await.unwrap().unwrap();
-    let keys = layout.keys;
+/// Probability of each key and key group of the layout being pressed next, from how the words
+/// that could be being typed go on. `end` is that of the word being finished instead.
+#[post("/next_key")]
+async fn next_key(
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    data: web::Json<PredictRequest>,
+) -> impl Responder {
+    let code = data.language.as_deref();
+    let (language, model, layout, touch, typed) =
+        match read_request(&config, &repo, code, &data.layout, &data.text).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+    let context = typed
+        .context
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
 
-    let result = repo
+    let smoothing = data.smoothing.as_ref().unwrap_or(&config.smoothing);
+    let result = model
         .bigrams
-        .find_predictions(second_to_last_word, last_word, keys.clone())
+        .find_predictions(
+            &context,
+            typed.last_word.as_deref(),
+            &typed.groups,
+            smoothing,
+        )
         .await;
-
-    match result {
-        Ok(data) => {
-            if !data.is_empty() {
-                let data = data
-                    .into_iter()
-                    .skip(query.offset.unwrap_or(0) as usize)
-                    .take(query.limit.unwrap_or(10) as usize)
-                    .collect::<Vec<Prediction>>();
-                return HttpResponse::Ok().json(json!({ "data": { "prediction": data } }));
-            }
-        }
+    let mut predictions = match result {
+        Ok(predictions) => predictions,
         Err(err) => {
-            return HttpResponse::InternalServerError().json(json!({
-                "error": err.to_string()
-            }));
+            return HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    };
+    if let (Some(touch), Some(last_word)) = (&touch, &typed.last_word) {
+        predictions = touch.rescore(predictions, last_word);
+    }
+
+    // Every word that could be being typed votes for the letter after those typed.
+    let position = typed.last_word.map_or(0, |word| word.chars().count());
+    let mut next = HashMap::<char, f64>::new();
+    let (mut end, mut total) = (0.0, 0.0);
+    for prediction in &predictions {
+        total += prediction.probability;
+        let letter = prediction.word.chars().nth(position);
+        match letter.filter(|_| prediction.word != SENTENCE_END) {
+            Some(letter) => *next.entry(letter).or_default() += prediction.probability,
+            None => end += prediction.probability,
         }
     }
+    let share = |probability: f64| {
+        if total > 0.0 {
+            probability / total
+        } else {
+            0.0
+        }
+    };
+    let probability = |key: String, letters: &str| {
+        let letters = language
+            .normalizer
+            .normalize(letters)
+            .chars()
+            .collect::<HashSet<char>>();
+        let probability = letters.iter().flat_map(|letter| next.get(letter)).sum();
+        KeyProbability {
+            key,
+            probability: share(probability),
+        }
+    };
+    let ranked = |mut probabilities: Vec<KeyProbability>| {
+        probabilities.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+        probabilities
+    };
+
+    let keys = layout
+        .typed_letters()
+        .into_iter()
+        .map(|(key, letters)| probability(key, &letters))
+        .collect();
+    let groups = layout
+        .key_groups()
+        .into_iter()
+        .map(|group| probability(group.clone(), &group))
+        .collect();
 
-    let result = repo
+    HttpResponse::Ok().json(json!({
+        "data": {
+            "language": language.code,
+            "keys": ranked(keys),
+            "groups": ranked(groups),
+            "end": share(end),
+        }
+    }))
+}
+
+/// Decodes a swipe over a layout with key positions into the words it most likely spells,
+/// given the text before it.
+#[post("/swipe")]
+async fn swipe(
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+    data: web::Json<SwipeRequest>,
+    query: web::Query<Pagination>,
+) -> impl Responder {
+    let code = data.language.as_deref();
+    let (language, model, _, touch, typed) =
+        match read_request(&config, &repo, code, &data.layout, &data.text).await {
+            Ok(request) => request,
+            Err(resp) => return resp,
+        };
+    let Some(touch) = touch else {
+        return bad_request("Layout has no key positions".to_string());
+    };
+
+    let mut points = data.points.clone();
+    points.sort_by(|a, b| a.t.total_cmp(&b.t));
+    let gesture = points
+        .iter()
+        .map(|point| (point.x, point.y))
+        .collect::<Vec<Point>>();
+    let Some(start) = gesture.first() else {
+        return bad_request("Gesture has no points".to_string());
+    };
+    // Candidates start with a key the gesture starts near.
+    let near = touch.keys_near(*start);
+    let Some(first) = near.first() else {
+        return HttpResponse::Ok().json(json!({
+            "data": { "language": language.code, "prediction": [] }
+        }));
+    };
+    let first = first.to_string();
+    let groups = [near.iter().collect::<String>()];
+
+    let context = typed
+        .context
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
+
+    let smoothing = data.smoothing.as_ref().unwrap_or(&config.smoothing);
+    let result = model
         .bigrams
-        .find_predictions(None, last_word, keys.clone())
+        .find_predictions(&context, Some(&first), &groups, smoothing)
         .await;
+    let data = match result {
+        Ok(data) => paginate(touch.decode(data, &gesture), &query),
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    };
 
-    match result {
-        Ok(data) => {
-            let data = data
-                .into_iter()
-                .skip(query.offset.unwrap_or(0) as usize)
-                .take(query.limit.unwrap_or(10) as usize)
-                .collect::<Vec<Prediction>>();
-            HttpResponse::Ok().json(json!({ "data": { "prediction": data } }))
-        }
-        Err(err) => HttpResponse::InternalServerError().json(json!({
-            "error": err.to_string()
+    match present(model, data, typed.upper, typed.capital).await {
+        Ok(data) => HttpResponse::Ok().json(json!({
+            "data": { "language": language.code, "prediction": data }
         })),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
     }
 }
 
+/// Matching runs on normalized words, but suggestions are shown as people write them: in their
+/// most frequent form, upper-cased or capitalized as asked.
+async fn present(
+    model: &LanguageModel,
+    data: Vec<Prediction>,
+    upper: bool,
+    capital: bool,
+) -> Result<Vec<Prediction>, StoreError> {
+    let words = data
+        .iter()
+        .map(|prediction| prediction.word.as_str())
+        .collect::<Vec<&str>>();
+    let forms = model.bigrams.find_forms(&words).await?;
+
+    let data = data
+        .into_iter()
+        .map(|prediction| {
+            let word = forms
+                .get(&prediction.word)
+                .cloned()
+                .unwrap_or(prediction.word);
+            let word = if upper {
+                word.to_uppercase()
+            } else if capital {
+                capitalize(&word)
+            } else {
+                word
+            };
+            Prediction { word, ..prediction }
+        })
+        .collect();
+    Ok(data)
+}
+
+fn paginate(data: Vec<Prediction>, query: &Pagination) -> Vec<Prediction> {
+    // The end of a sentence is a valid outcome for the model, but not a word to suggest.
+    data.into_iter()
+        .filter(|prediction| prediction.word != SENTENCE_END)
+        .skip(query.offset.unwrap_or(0) as usize)
+        .take(query.limit.unwrap_or(10) as usize)
+        .collect()
+}
+
 #[get("/process_text")]
 async fn get_process_text(
-    repo: web::Data<MongoRepo>,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
     query: web::Query<Pagination>,
+    language: web::Query<LanguageQuery>,
 ) -> impl Responder {
-    let result = repo.bigrams.find_all(query.into_inner()).await;
+    let (_, model) = match find_language(&config, &repo, language.language.as_deref()) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+
+    let result = model.bigrams.find_all(query.into_inner()).await;
 
     match result {
         Ok(data) => {
@@ -139,16 +807,76 @@ async fn get_process_text(
 mod tests {
     use super::*;
 
+    use std::sync::Arc;
+
     use actix_web::test;
+    use async_trait::async_trait;
+
+    use crate::{
+        controllers::fixtures::app_with,
+        models::{
+            bigrams::{BigramModel, ContextCounts, SurfaceForm},
+            layouts::{
+                fixtures::{layout, qwerty, touch_qwerty},
+                LayoutKind,
+            },
+        },
+        repositories::{BigramStore, Context},
+    };
+
+    /// Store whose writes all fail, as when the database goes away in the middle of an upload.
+    struct Unwritable;
+
+    #[async_trait]
+    impl BigramStore for Unwritable {
+        async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+            Err(Box::new(PartialWrite {
+                failed: (0..ngrams.len()).collect(),
+                total: ngrams.len(),
+                message: "Database unavailable".to_string(),
+            }))
+        }
+
+        async fn find_counts(
+            &self,
+            _: Context<'_>,
+            _: Option<&str>,
+            _: &[String],
+        ) -> Result<ContextCounts, StoreError> {
+            Ok(ContextCounts::default())
+        }
+
+        async fn find_all(&self, _: Pagination) -> Result<Vec<BigramModel>, StoreError> {
+            Ok(vec![])
+        }
+
+        async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+            Ok(vec![])
+        }
+
+        async fn upsert_forms(&self, _: &[SurfaceForm]) -> Result<(), StoreError> {
+            Ok(())
+        }
+
+        async fn find_forms(&self, _: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+            Ok(HashMap::new())
+        }
+    }
+
+    fn unwritable() -> Repo {
+        Repo {
+            models: vec![LanguageModel {
+                language: "en".to_string(),
+                bigrams: Arc::new(Unwritable),
+                artifact: None,
+            }],
+            ..Repo::memory(&["en"])
+        }
+    }
 
     #[actix_web::test]
     async fn test_process_text() {
-        let app = test::init_service(
-            actix_web::App::new()
-                .app_data(web::Data::new(MongoRepo::init("test").await))
-                .configure(register_routes),
-        )
-        .await;
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
 
         let body = json!({ "text": "Hello world and hello everyone, this is a test of the process_text endpoint" });
 
@@ -160,7 +888,741 @@ mod tests {
         let resp = actix_web::test::call_service(&app, req).await;
 
         assert!(resp.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn test_process_text_aggregates_repeated_ngrams() {
+        let app = app_with(Repo::sqlite(":memory:", &["en"]), Config::default(), "").await;
+
+        let body = json!({ "text": "the cat and the cat" });
+        let req = test::TestRequest::post()
+            .uri("/process_text")
+            .set_json(&body)
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["bigram_count"], 4);
+        assert_eq!(resp["data"]["ngram_count"], 17);
+
+        let req = test::TestRequest::get()
+            .uri("/process_text?limit=1")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let bigram = &resp["data"]["bigrams"][0];
+        assert_eq!(bigram["count"], 2);
+        assert_eq!(bigram["second"], "cat");
+    }
 
-        MongoRepo::drop("test").await;
+    #[actix_web::test]
+    async fn test_upload_corpus() {
+        use std::io::Write;
+
+        let app = app_with(Repo::sqlite(":memory:", &["en"]), Config::default(), "").await;
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"the cat ran").unwrap();
+        let gzip = encoder.finish().unwrap();
+
+        let upload = |files: Vec<(&str, Vec<u8>)>| {
+            let mut body = vec![];
+            for (name, content) in files {
+                body.extend_from_slice(
+                    format!(
+                        "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\r\n"
+                    )
+                    .as_bytes(),
+                );
+                body.extend_from_slice(&content);
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(b"--boundary--\r\n");
+
+            test::TestRequest::post()
+                .uri("/process_text/upload")
+                .insert_header(("content-type", "multipart/form-data; boundary=boundary"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let req = upload(vec![
+            ("one.txt", b"the cat sat".to_vec()),
+            ("two.txt.gz", gzip),
+        ]);
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["files"][1]["word_count"], 3);
+        // Files are separate documents, so "sat the" is never counted.
+        assert_eq!(resp["data"]["bigram_count"], 4);
+
+        let req = test::TestRequest::get()
+            .uri("/process_text?limit=1")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["bigrams"][0]["count"], 2);
+
+        let req = upload(vec![
+            ("one.txt", b"the cat sat".to_vec()),
+            ("corpus.pdf", b"the".to_vec()),
+        ]);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "Reject unsupported files");
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(resp["data"]["files"][0]["name"], "one.txt");
+        assert!(
+            resp["data"]["last_flushed_file"].is_null(),
+            "Nothing was written"
+        );
+
+        let app = app_with(unwritable(), Config::default(), "").await;
+        let req = upload(vec![("one.txt", b"the cat sat".to_vec())]);
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 500);
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(resp["data"]["files"][0]["bigram_count"], 2);
+        assert!(resp["data"]["last_flushed_file"].is_null());
+        assert_eq!(resp["data"]["ngrams_written"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_process_ndjson() {
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
+
+        let body = "{\"text\": \"the cat sat\"}\n\nnot json\n{\"text\": \"on the mat\"}\n\
+            {\"text\": \"le chat\", \"language\": \"fr\"}";
+        let req = test::TestRequest::post()
+            .uri("/process_text/ndjson")
+            .insert_header(("content-type", "application/x-ndjson"))
+            .set_payload(body)
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let records = resp["data"]["records"].as_array().unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0]["bigram_count"], 2);
+        assert!(records[1]["error"].is_string(), "Report invalid records");
+        assert_eq!(records[2]["bigram_count"], 2);
+        assert!(records[3]["error"].is_string(), "Report unknown languages");
+        // No n-gram spans "sat" and "on".
+        assert_eq!(resp["data"]["bigram_count"], 4);
+    }
+
+    #[actix_web::test]
+    async fn test_process_ndjson_write_error() {
+        let app = app_with(unwritable(), Config::default(), "").await;
+
+        let req = test::TestRequest::post()
+            .uri("/process_text/ndjson")
+            .insert_header(("content-type", "application/x-ndjson"))
+            .set_payload("{\"text\": \"the cat sat\"}\n{\"text\": \"on the mat\"}")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 500);
+
+        let resp: serde_json::Value = test::read_body_json(resp).await;
+        assert!(resp["error"].is_string());
+        assert_eq!(resp["data"]["records"].as_array().unwrap().len(), 2);
+        assert_eq!(resp["data"]["records"][1]["bigram_count"], 2);
+        assert!(
+            resp["data"]["last_flushed_record"].is_null(),
+            "Nothing was written"
+        );
+        assert_eq!(resp["data"]["ngrams_written"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_predict_surface_forms() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let app = app_with(repo, Config::default(), "").await;
+
+        for text in ["una canción", "otra canción", "sin cancion"] {
+            let req = test::TestRequest::post()
+                .uri("/process_text")
+                .set_json(json!({ "text": text }))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        // Typed without the accent, matched on the folded key, shown with it.
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "una cancion", "layout": "qwerty" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["prediction"][0]["word"], "canción");
+    }
+
+    #[actix_web::test]
+    async fn test_languages() {
+        let repo = Repo::memory(&["en", "es"]);
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let mut config = Config::default();
+        config.languages.push(crate::config::Language {
+            code: "es".to_string(),
+            normalizer: crate::normalization::Normalizer {
+                accents: crate::normalization::AccentFolding::Keep,
+                ..Default::default()
+            },
+        });
+        let app = app_with(repo, config, "").await;
+
+        for (text, language) in [("the cat sat", None), ("el gato comió", Some("es"))] {
+            let req = test::TestRequest::post()
+                .uri("/process_text")
+                .set_json(json!({ "text": text, "language": language }))
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "el gato c", "layout": "qwerty", "language": "es" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["prediction"][0]["word"], "comió");
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "el gato c", "layout": "qwerty" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let words = resp["data"]["prediction"].as_array().unwrap();
+        assert!(
+            words.iter().all(|word| word["word"] == "cat"),
+            "Only English words"
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/process_text?language=es&limit=100")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let bigrams = resp["data"]["bigrams"].as_array().unwrap();
+        assert!(bigrams.iter().any(|bigram| bigram["second"] == "comió"));
+        assert!(bigrams.iter().all(|bigram| bigram["second"] != "cat"));
+
+        let req = test::TestRequest::post()
+            .uri("/process_text")
+            .set_json(json!({ "text": "le chat", "language": "fr" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400, "Unknown language");
+    }
+
+    #[actix_web::test]
+    async fn test_identified_language() {
+        let repo = Repo::memory(&["en", "es"]);
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let mut config = Config::default();
+        config.languages.push(crate::config::Language {
+            code: "es".to_string(),
+            normalizer: Default::default(),
+        });
+        config.identifier = crate::language_id::LanguageIdentifier::builtin(&["en", "es"]);
+        let app = app_with(repo, config, "").await;
+
+        let req = test::TestRequest::post()
+            .uri("/process_text")
+            .set_json(json!({ "text": "el perro come en la casa de la familia" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["language"], "es");
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "el perro c", "layout": "qwerty" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["language"], "es");
+        assert_eq!(resp["data"]["prediction"][0]["word"], "come");
+
+        // A named language always wins over the identified one.
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "el perro c", "layout": "qwerty", "language": "en" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["prediction"], json!([]));
+    }
+
+    #[actix_web::test]
+    async fn test_predict_cyrillic() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts
+            .create(&layout(
+                "йцукен",
+                LayoutKind::Keyboard,
+                &["йцукенгшщз", "фывапролдж", "ячсмитьбюэ"],
+            ))
+            .await
+            .unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "Мы идём домой. Мы идём домой. Мы идём в парк.",
+        )
+        .await;
+
+        for (text, expected) in [
+            ("мы идём ", "домой"),
+            ("Мы идём в ", "парк"),
+            ("Мы и", "идём"),
+        ] {
+            let req = test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": text, "layout": "йцукен" }))
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["data"]["prediction"][0]["word"], expected, "{text}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_predict_decomposed_keys() {
+        let corpus = "Мы идём домой. Мы иду в парк.";
+        let app = app_with(Repo::memory(&["en"]), Config::default(), corpus).await;
+
+        // "ё" is written as "е" and a combining diaeresis, and shares a key with "ж".
+        let layout = json!({
+            "name": "russian",
+            "keys": [ "йцукенгшщз", "фывапролдж", "ячсмитьбюе\u{308}" ],
+            "groups": [ "же\u{308}" ],
+        });
+        let req = test::TestRequest::post()
+            .uri("/layouts")
+            .set_json(&layout)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+
+        for text in ["Мы идё", "Мы идж"] {
+            let req = test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": text, "layout": "russian" }))
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["data"]["prediction"][0]["word"], "идём", "{text}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_predict_groups() {
+        let repo = Repo::memory(&["en"]);
+        let rows = ["qwert", "yuiop", "asdfg", "hjkl", "zxcvb", "nm"];
+        for (name, groups) in [("split", Some(vec!["asdf".to_string()])), ("plain", None)] {
+            repo.layouts
+                .create(&LayoutModel {
+                    groups,
+                    ..layout(name, LayoutKind::Keyboard, &rows)
+                })
+                .await
+                .unwrap();
+        }
+        let app = app_with(
+            repo,
+            Config::default(),
+            "The apple. The dog. The far. The fox. The gift. The sat.",
+        )
+        .await;
+
+        let request = |layout: &str| {
+            test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": "the d", "layout": layout }))
+                .to_request()
+        };
+        let words = |resp: serde_json::Value| {
+            let mut words = resp["data"]["prediction"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|prediction| prediction["word"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>();
+            words.sort();
+            words
+        };
+
+        // "d" stands for any key of its group, and only for itself without groups.
+        let resp = test::call_and_read_body_json(&app, request("split")).await;
+        assert_eq!(words(resp), ["apple", "dog", "far", "fox", "sat"]);
+        let resp = test::call_and_read_body_json(&app, request("plain")).await;
+        assert_eq!(words(resp), ["dog"]);
+    }
+
+    #[actix_web::test]
+    async fn test_predict_keypad() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts
+            .create(&layout(
+                "phone",
+                LayoutKind::Keypad,
+                &["123", "456", "789", "*0#"],
+            ))
+            .await
+            .unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "I am goodbye. I am goodbye. I am goodbye. I am home. I am home. I am good. I am in.",
+        )
+        .await;
+
+        let request = |text: &str| {
+            test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": text, "layout": "phone" }))
+                .to_request()
+        };
+        let words = |resp: serde_json::Value| {
+            resp["data"]["prediction"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|prediction| prediction["word"].as_str().unwrap().to_string())
+                .collect::<Vec<String>>()
+        };
+
+        // Words spelled in full come first, then longer ones, each by probability.
+        let resp = test::call_and_read_body_json(&app, request("I am 4663")).await;
+        assert_eq!(words(resp), ["home", "good", "goodbye"]);
+
+        // Keys without letters are skipped, but "0" and "#" end the word like a space.
+        let resp = test::call_and_read_body_json(&app, request("I am 1*4663")).await;
+        assert_eq!(words(resp), ["home", "good", "goodbye"]);
+        let resp = test::call_and_read_body_json(&app, request("I am 0")).await;
+        assert_eq!(words(resp)[0], "goodbye");
+        let resp = test::call_and_read_body_json(&app, request("I 26#4663")).await;
+        assert_eq!(words(resp), ["home", "good", "goodbye"]);
+
+        let resp = test::call_and_read_body_json(&app, request("I am 46")).await;
+        assert_eq!(words(resp), ["in", "goodbye", "home", "good"]);
+
+        // Sequences start sentences with a capital like typed words do.
+        let resp = test::call_and_read_body_json(&app, request("4")).await;
+        assert_eq!(words(resp), ["I"]);
+
+        let resp = test::call_and_read_body_json(&app, request("I am 41")).await;
+        assert_eq!(words(resp), ["goodbye", "home", "good", "in"]);
+    }
+
+    #[actix_web::test]
+    async fn test_predict_near_misses() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts.create(&touch_qwerty()).await.unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "say hello. say jello. say jello. say jello. say hallo.",
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "say hrllo", "layout": "touch" }))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+
+        // "jello" is more frequent, but "hello" only misses one key by one. "hallo" misses a key
+        // out of reach, so it is only a correction.
+        assert_eq!(prediction.len(), 3);
+        assert_eq!(prediction[0]["word"], "hello");
+        assert_eq!(prediction[1]["word"], "jello");
+        assert_eq!(prediction[2]["word"], "hallo");
+        assert_eq!(prediction[2]["type"], "correction");
+        // Completions and corrections share the probability by how often each follows "say".
+        let probabilities = prediction
+            .iter()
+            .map(|prediction| prediction["probability"].as_f64().unwrap())
+            .collect::<Vec<f64>>();
+        assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+
+    #[actix_web::test]
+    async fn test_predict_corrections() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts
+            .create(&LayoutModel {
+                positions: None,
+                ..touch_qwerty()
+            })
+            .await
+            .unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "I need help. I need helium. I need a hand. Go home.",
+        )
+        .await;
+
+        let request = |text: &str| {
+            test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": text, "layout": "touch" }))
+                .to_request()
+        };
+
+        // Nothing starts with "hwlp", but "w" is next to the "e" of "help".
+        let resp: serde_json::Value =
+            test::call_and_read_body_json(&app, request("I need hwlp")).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+        assert_eq!(prediction.len(), 1);
+        assert_eq!(prediction[0]["word"], "help");
+        assert_eq!(prediction[0]["type"], "correction");
+        // Alone, the correction gets all of the probability, as a lone completion would.
+        assert!((prediction[0]["probability"].as_f64().unwrap() - 1.0).abs() < 1e-9);
+
+        // Words that start with the letters typed are completions.
+        let resp: serde_json::Value =
+            test::call_and_read_body_json(&app, request("I need hom")).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+        let words = prediction
+            .iter()
+            .map(|prediction| {
+                (
+                    prediction["word"].as_str().unwrap(),
+                    prediction["type"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<(&str, &str)>>();
+        assert_eq!(words, [("home", "completion")]);
+
+        // Swapped letters are one slip.
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, request("Go hoem")).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+        assert_eq!(prediction.len(), 1);
+        assert_eq!(prediction[0]["word"], "home");
+        assert_eq!(prediction[0]["type"], "correction");
+
+        // With enough completions, "help" and "helium" are not suggested for "hol".
+        let req = test::TestRequest::post()
+            .uri("/process_text")
+            .set_json(json!({ "text": "I need a hole. I need a hold. I need a holiday." }))
+            .to_request();
+        test::call_service(&app, req).await;
+        let resp: serde_json::Value =
+            test::call_and_read_body_json(&app, request("I need hol")).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+        assert_eq!(prediction.len(), 3);
+        assert!(prediction
+            .iter()
+            .all(|prediction| prediction["type"] == "completion"));
+    }
+
+    #[actix_web::test]
+    async fn test_swipe() {
+        let repo = Repo::memory(&["en"]);
+        let layout = touch_qwerty();
+        let positions = layout.positions.clone().unwrap();
+        repo.layouts.create(&layout).await.unwrap();
+        repo.layouts
+            .create(&LayoutModel {
+                name: Some("plain".to_string()),
+                positions: None,
+                ..layout
+            })
+            .await
+            .unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "I said hello. I said help. I said hold. I said hole.",
+        )
+        .await;
+
+        // A swipe from h through e and l to o, its points sent out of order.
+        let points = ["h", "e", "l", "o"]
+            .into_iter()
+            .enumerate()
+            .map(|(t, key)| {
+                let key = &positions[key];
+                json!({ "x": key.x + 0.1, "y": key.y - 0.2, "t": t })
+            })
+            .rev()
+            .collect::<Vec<serde_json::Value>>();
+        let request = |text: &str, layout: &str| {
+            test::TestRequest::post()
+                .uri("/swipe")
+                .set_json(json!({ "text": text, "layout": layout, "points": points }))
+                .to_request()
+        };
+
+        let resp: serde_json::Value =
+            test::call_and_read_body_json(&app, request("I said ", "touch")).await;
+        let prediction = resp["data"]["prediction"].as_array().unwrap();
+        assert_eq!(prediction[0]["word"], "hello");
+        assert!(prediction
+            .iter()
+            .all(|prediction| prediction["word"] != "I"));
+
+        // Swipes starting a sentence are capitalized like typed words.
+        let resp: serde_json::Value =
+            test::call_and_read_body_json(&app, request("", "touch")).await;
+        assert_eq!(resp["data"]["prediction"][0]["word"], "Hello");
+
+        let resp = test::call_service(&app, request("I said ", "plain")).await;
+        assert_eq!(resp.status(), 400);
+        let resp = test::call_service(&app, request("I said ", "missing")).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_next_key() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let keypad = layout("phone", LayoutKind::Keypad, &["123", "456", "789", "*0#"]);
+        repo.layouts.create(&keypad).await.unwrap();
+        let app = app_with(
+            repo,
+            Config::default(),
+            "The cat sat. The car is red. The cart is full. The dog ran.",
+        )
+        .await;
+
+        let request = |text: &str, layout: &str| {
+            test::TestRequest::post()
+                .uri("/next_key")
+                .set_json(json!({ "text": text, "layout": layout }))
+                .to_request()
+        };
+        let probability = |resp: &serde_json::Value, list: &str, key: &str| {
+            resp["data"][list]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|probability| probability["key"] == key)
+                .map(|probability| probability["probability"].as_f64().unwrap())
+                .unwrap()
+        };
+
+        // "ca" goes on as "cat", "car" or "cart", and "t" and "r" share a group.
+        let resp = test::call_and_read_body_json(&app, request("The ca", "qwerty")).await;
+        assert!((probability(&resp, "keys", "r") - 2.0 / 3.0).abs() < 1e-9);
+        assert!((probability(&resp, "keys", "t") - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(probability(&resp, "keys", "a"), 0.0);
+        assert!((probability(&resp, "groups", "rtfgvb") - 1.0).abs() < 1e-9);
+        assert_eq!(resp["data"]["keys"][0]["key"], "r");
+        assert_eq!(resp["data"]["end"], 0.0);
+
+        // "car" stands for "cat" too, both finished, while "cart" goes on.
+        let resp = test::call_and_read_body_json(&app, request("The car", "qwerty")).await;
+        assert!((probability(&resp, "keys", "t") - 1.0 / 3.0).abs() < 1e-9);
+        assert!((resp["data"]["end"].as_f64().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+
+        // On a keypad, "22" spells "ca", and "r" and "t" are on keys 7 and 8.
+        let resp = test::call_and_read_body_json(&app, request("The 22", "phone")).await;
+        assert!((probability(&resp, "keys", "7") - 2.0 / 3.0).abs() < 1e-9);
+        assert!((probability(&resp, "keys", "8") - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(resp["data"]["groups"], json!([]));
+
+        let resp = test::call_service(&app, request("The ca", "missing")).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[actix_web::test]
+    async fn test_predict_casing() {
+        let repo = Repo::memory(&["en"]);
+        repo.layouts.create(&qwerty()).await.unwrap();
+        let corpus = "We visited Paris and Rome. The trip to Paris was great.";
+        let app = app_with(repo, Config::default(), corpus).await;
+
+        let cases = [
+            ("we visited ", "Paris"),
+            ("we visited p", "Paris"),
+            ("WE VISITED ", "PARIS"),
+            ("we visited PA", "PARIS"),
+            ("Paris was great. ", "The"),
+            ("Paris was great. t", "the"),
+            ("the trip ", "to"),
+        ];
+
+        for (text, expected) in cases {
+            let req = test::TestRequest::post()
+                .uri("/predict")
+                .set_json(json!({ "text": text, "layout": "qwerty" }))
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(resp["data"]["prediction"][0]["word"], expected, "{text:?}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_predict() {
+        check_predict(Repo::memory(&["en"])).await;
+    }
+
+    #[actix_web::test]
+    async fn test_predict_sqlite() {
+        check_predict(Repo::sqlite(":memory:", &["en"])).await;
+    }
+
+    #[actix_web::test]
+    async fn test_predict_indexed() {
+        let repo = Repo::sqlite(":memory:", &["en"]);
+        let unigram = BigramModel {
+            id: None,
+            first: "".to_string(),
+            second: "unrelated".to_string(),
+            count: 1,
+        };
+        repo.models[0]
+            .bigrams
+            .upsert_many(&[unigram])
+            .await
+            .unwrap();
+
+        check_predict(repo.with_prefix_index().await).await;
+    }
+
+    async fn check_predict(repo: Repo) {
+        repo.layouts.create(&qwerty()).await.unwrap();
+
+        let app = app_with(repo, Config::default(), "").await;
+
+        let body = json!({ "text": "the cat sat on the mat and the cat ran" });
+
+        let req = test::TestRequest::post()
+            .uri("/process_text")
+            .set_json(&body)
+            .to_request();
+
+        let resp = test::call_service(&app, req).await;
+
+        assert!(resp.status().is_success(), "Process text");
+
+        let cases = [
+            ("the ", vec!["cat"]),
+            ("sat on the ", vec!["mat"]),
+            ("and the ", vec!["cat"]),
+            ("the cat ", vec!["ran", "sat"]),
+            ("on the ", vec!["mat"]),
+            ("a cat ", vec!["ran", "sat"]),
+            ("the cat r", vec!["ran"]),
+            ("t", vec!["the"]),
+            ("The cat ran. ", vec!["The"]),
+            ("the mat. a cat ", vec!["ran", "sat"]),
+        ];
+
+        for (text, expected) in cases {
+            let body = json!({ "text": text, "layout": "qwerty" });
+
+            let req = test::TestRequest::post()
+                .uri("/predict")
+                .set_json(&body)
+                .to_request();
+
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+            let words = resp["data"]["prediction"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|prediction| prediction["word"].as_str().unwrap())
+                .collect::<Vec<&str>>();
+            assert_eq!(words, expected, "Predict {text:?}");
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/predict")
+            .set_json(json!({ "text": "the ", "layout": "missing" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404, "Predict with an unknown layout");
     }
 }
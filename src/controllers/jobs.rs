@@ -0,0 +1,184 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use bson::oid::ObjectId;
+use serde::Deserialize;
+use serde_json::json;
+
+use super::{bad_request, detect_language};
+use crate::{
+    config::Config,
+    ingestion::{run_job, NgramCounter},
+    models::{bigrams::ProcessTextRequest, jobs::JobModel},
+    repositories::Repo,
+};
+
+pub fn register_routes(cfg: &mut web::ServiceConfig) {
+    let scope = web::scope("jobs");
+    cfg.service(
+        scope
+            .service(create_job)
+            .service(get_job)
+            .service(cancel_job),
+    );
+}
+
+#[derive(Deserialize)]
+struct JobPath {
+    job_id: String,
+}
+
+fn job_not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "error": "Job not found" }))
+}
+
+/// Queues the text like `POST /process_text` would count it and returns right away.
+#[post("")]
+async fn create_job(
+    data: web::Json<ProcessTextRequest>,
+    repo: web::Data<Repo>,
+    config: web::Data<Config>,
+) -> impl Responder {
+    let code = data.language.as_deref();
+    let (language, model) = match detect_language(&config, &repo, code, &data.text) {
+        Ok(found) => found,
+        Err(err) => return bad_request(err),
+    };
+
+    let mut job = JobModel {
+        language: Some(language.code.clone()),
+        owner: Some(config.instance.clone()),
+        ..JobModel::default()
+    };
+    let result = repo.jobs.create(&job).await;
+
+    match result {
+        Ok(id) => {
+            job.id = Some(id);
+            let text = data.into_inner().text;
+            actix_web::rt::spawn(run_job(
+                repo.get_ref().clone(),
+                model.bigrams.clone(),
+                job,
+                text,
+                NgramCounter::new(&config, language),
+            ));
+            HttpResponse::Accepted().json(json!({ "data": { "id": id.to_hex() } }))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
+    }
+}
+
+#[get("/{job_id}")]
+async fn get_job(path: web::Path<JobPath>, repo: web::Data<Repo>) -> impl Responder {
+    let Ok(id) = ObjectId::parse_str(&path.job_id) else {
+        return job_not_found();
+    };
+
+    match repo.jobs.find(id).await {
+        Ok(Some(data)) => HttpResponse::Ok().json(json!({ "data": data })),
+        Ok(None) => job_not_found(),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
+    }
+}
+
+/// Stops a queued or running job. Counts it already wrote are kept.
+#[post("/{job_id}/cancel")]
+async fn cancel_job(path: web::Path<JobPath>, repo: web::Data<Repo>) -> impl Responder {
+    let Ok(id) = ObjectId::parse_str(&path.job_id) else {
+        return job_not_found();
+    };
+
+    let cancelled = match repo.jobs.cancel(id).await {
+        Ok(cancelled) => cancelled,
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(json!({ "error": err.to_string() }))
+        }
+    };
+    if let Some(job) = cancelled {
+        return HttpResponse::Ok().json(json!({ "data": job }));
+    }
+
+    match repo.jobs.find(id).await {
+        Ok(Some(_)) => HttpResponse::Conflict().json(json!({ "error": "Job already finished" })),
+        Ok(None) => job_not_found(),
+        Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test;
+
+    use crate::{controllers::fixtures::app_with, models::jobs::JobStatus};
+
+    #[actix_web::test]
+    async fn test_job_completes() {
+        let repo = Repo::sqlite(":memory:", &["en"]);
+        let app = app_with(repo.clone(), Config::default(), "").await;
+
+        let body = json!({ "text": "the cat sat on the mat" });
+        let req = test::TestRequest::post()
+            .uri("/jobs")
+            .set_json(&body)
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let id = resp["data"]["id"].as_str().unwrap().to_string();
+
+        let mut job = serde_json::Value::Null;
+        for _ in 0..100 {
+            let req = test::TestRequest::get()
+                .uri(&format!("/jobs/{id}"))
+                .to_request();
+            let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            job = resp["data"].clone();
+            if job["status"] == "completed" {
+                break;
+            }
+            actix_web::rt::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(job["status"], "completed");
+        assert_eq!(job["words_processed"], 6);
+        assert_eq!(job["bigrams_written"], 5);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/jobs/{id}/cancel"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 409, "Cannot cancel a finished job");
+    }
+
+    #[actix_web::test]
+    async fn test_cancel_job() {
+        let repo = Repo::sqlite(":memory:", &["en"]);
+        let job = JobModel {
+            status: JobStatus::Running,
+            words_processed: 500,
+            ..JobModel::default()
+        };
+        let id = repo.jobs.create(&job).await.unwrap();
+        let app = app_with(repo.clone(), Config::default(), "").await;
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/jobs/{}/cancel", id.to_hex()))
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["status"], "cancelled");
+        assert_eq!(
+            resp["data"]["words_processed"], 500,
+            "Keeps the worker's progress"
+        );
+
+        // A worker picking the job up afterwards must not resume it.
+        let job = JobModel {
+            id: Some(id),
+            status: JobStatus::Running,
+            ..JobModel::default()
+        };
+        assert!(!repo.jobs.update(&job).await.unwrap());
+
+        let req = test::TestRequest::get().uri("/jobs/unknown").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+    }
+}
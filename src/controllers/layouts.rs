@@ -1,8 +1,15 @@
+use std::collections::HashSet;
+
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
 use serde::Deserialize;
 use serde_json::json;
+use unicode_normalization::UnicodeNormalization;
 
-use crate::{models::layouts::LayoutModel, repositories::MongoRepo};
+use crate::{
+    models::layouts::{LayoutKind, LayoutModel},
+    repositories::Repo,
+    utils::row_keys,
+};
 
 pub fn register_routes(cfg: &mut web::ServiceConfig) {
     let scope = web::scope("layouts");
@@ -21,8 +28,92 @@ struct LayoutPath {
     layout_name: String,
 }
 
+/// Puts every key of the layout in NFC, the form typed text is normalized to, so that keys
+/// written decomposed, like "е\u{308}", match the letters typed with them.
+fn normalize_layout(layout: LayoutModel) -> LayoutModel {
+    let nfc = |text: &String| text.nfc().collect::<String>();
+    LayoutModel {
+        keys: layout.keys.iter().map(nfc).collect(),
+        groups: layout
+            .groups
+            .as_ref()
+            .map(|groups| groups.iter().map(nfc).collect()),
+        letters: layout.letters.as_ref().map(|letters| {
+            letters
+                .iter()
+                .map(|(key, letters)| (nfc(key), nfc(letters)))
+                .collect()
+        }),
+        positions: layout.positions.as_ref().map(|positions| {
+            positions
+                .iter()
+                .map(|(key, position)| (nfc(key), position.clone()))
+                .collect()
+        }),
+        ..layout
+    }
+}
+
+/// Checks that the layout has keys and that its groups only use them, each key at most once.
+/// Keypad letters must belong to keys on the layout, each letter to one key, and so must key
+/// positions, with a size. Positions, when given, must place at least one key.
+fn check_layout(layout: &LayoutModel) -> Result<(), String> {
+    if layout.keys.is_empty() || layout.keys.iter().any(|row| row.is_empty()) {
+        return Err("Layout keys must have at least one row, and rows at least one key".into());
+    }
+
+    let keys = layout
+        .keys
+        .iter()
+        .flat_map(|row| row_keys(row))
+        .collect::<HashSet<&str>>();
+    let mut grouped = HashSet::new();
+    for group in layout.groups.iter().flatten() {
+        if group.is_empty() {
+            return Err("Layout groups must not be empty".into());
+        }
+        for key in row_keys(group) {
+            if !keys.contains(key) {
+                return Err(format!("Group key {key:?} is not on the layout"));
+            }
+            if !grouped.insert(key) {
+                return Err(format!("Key {key:?} is in more than one group"));
+            }
+        }
+    }
+
+    if layout
+        .positions
+        .as_ref()
+        .is_some_and(|positions| positions.is_empty())
+    {
+        return Err("Layout positions must place at least one key".into());
+    }
+    for (key, position) in layout.positions.iter().flatten() {
+        if !keys.contains(key.as_str()) {
+            return Err(format!("Positioned key {key:?} is not on the layout"));
+        }
+        if !(position.width > 0.0 && position.height > 0.0) {
+            return Err(format!("Key {key:?} must have a positive width and height"));
+        }
+    }
+
+    if layout.kind == LayoutKind::Keypad {
+        let mut typed = HashSet::new();
+        for (key, letters) in layout.key_letters() {
+            if !keys.contains(key.as_str()) {
+                return Err(format!("Keypad key {key:?} is not on the layout"));
+            }
+            if let Some(letter) = letters.chars().find(|letter| !typed.insert(*letter)) {
+                return Err(format!("Letter {letter:?} is on more than one key"));
+            }
+        }
+    }
+    Ok(())
+}
+
 #[get("")]
-async fn get_layouts(repo: web::Data<MongoRepo>) -> impl Responder {
+async fn get_layouts(repo: web::Data<Repo>) -> impl Responder {
     let layouts = repo.layouts.find_all().await;
 
     match layouts {
@@ -32,31 +123,25 @@ async fn get_layouts(repo: web::Data<MongoRepo>) -> impl Responder {
 }
 
 #[post("")]
-async fn create_layout(
-    layout: web::Json<LayoutModel>,
-    repo: web::Data<MongoRepo>,
-) -> impl Responder {
+async fn create_layout(layout: web::Json<LayoutModel>, repo: web::Data<Repo>) -> impl Responder {
+    let layout = normalize_layout(layout.into_inner());
     if layout.name.is_none() {
         return HttpResponse::BadRequest().json(json!({ "error": "Layout name is required" }));
     }
-    if layout.keys.len() != 3 {
-        return HttpResponse::BadRequest().json(json!({ "error": "Layout keys must have 3 rows" }));
-    }
-    if layout.keys.iter().any(|row| row.len() != 10) {
-        return HttpResponse::BadRequest()
-            .json(json!({ "error": "Layout key rows must have 10 keys" }));
+    if let Err(err) = check_layout(&layout) {
+        return HttpResponse::BadRequest().json(json!({ "error": err }));
     }
 
     let result = repo.layouts.create(&layout).await;
 
     match result {
-        Ok(data) => HttpResponse::Created().json(json!({ "data": data })),
+        Ok(id) => HttpResponse::Created().json(json!({ "data": { "insertedId": id } })),
         Err(err) => HttpResponse::InternalServerError().json(json!({ "error": err.to_string() })),
     }
 }
 
 #[get("/{layout_name}")]
-async fn get_layout(path: web::Path<LayoutPath>, repo: web::Data<MongoRepo>) -> impl Responder {
+async fn get_layout(path: web::Path<LayoutPath>, repo: web::Data<Repo>) -> impl Responder {
     let result = repo.layouts.find(&path.layout_name).await;
 
     match result {
@@ -70,14 +155,11 @@ async fn get_layout(path: web::Path<LayoutPath>, repo: web::Data<MongoRepo>) ->
 async fn update_layout(
     path: web::Path<LayoutPath>,
     layout: web::Json<LayoutModel>,
-    repo: web::Data<MongoRepo>,
+    repo: web::Data<Repo>,
 ) -> impl Responder {
-    if layout.keys.len() != 3 {
-        return HttpResponse::BadRequest().json(json!({ "error": "Layout keys must have 3 rows" }));
-    }
-    if layout.keys.iter().any(|row| row.len() != 10) {
-        return HttpResponse::BadRequest()
-            .json(json!({ "error": "Layout key rows must have 10 keys" }));
+    let layout = normalize_layout(layout.into_inner());
+    if let Err(err) = check_layout(&layout) {
+        return HttpResponse::BadRequest().json(json!({ "error": err }));
     }
     let result = repo.layouts.update(&path.layout_name, &layout).await;
 
@@ -89,7 +171,7 @@ async fn update_layout(
 }
 
 #[delete("/{layout_name}")]
-async fn delete_layout(path: web::Path<LayoutPath>, repo: web::Data<MongoRepo>) -> impl Responder {
+async fn delete_layout(path: web::Path<LayoutPath>, repo: web::Data<Repo>) -> impl Responder {
     let result = repo.layouts.delete(&path.layout_name).await;
 
     match result {
@@ -105,14 +187,11 @@ mod tests {
 
     use actix_web::test;
 
+    use crate::{config::Config, controllers::fixtures::app_with};
+
     #[actix_web::test]
     async fn test_get_layouts() {
-        let app = test::init_service(
-            actix_web::App::new()
-                .app_data(web::Data::new(MongoRepo::init("test").await))
-                .configure(register_routes),
-        )
-        .await;
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
 
         let req = actix_web::test::TestRequest::get()
             .uri("/layouts")
@@ -125,12 +204,16 @@ mod tests {
 
     #[actix_web::test]
     async fn test_crud() {
-        let app = test::init_service(
-            actix_web::App::new()
-                .app_data(web::Data::new(MongoRepo::init("test").await))
-                .configure(register_routes),
-        )
-        .await;
+        check_crud(Repo::memory(&["en"])).await;
+    }
+
+    #[actix_web::test]
+    async fn test_crud_sqlite() {
+        check_crud(Repo::sqlite(":memory:", &["en"])).await;
+    }
+
+    async fn check_crud(repo: Repo) {
+        let app = app_with(repo, Config::default(), "").await;
 
         let qwerty = json!({
             "name": "qwertybad",
@@ -179,7 +262,126 @@ mod tests {
         let resp = test::call_service(&app, req).await;
 
         assert!(resp.status().is_client_error(), "Get deleted layout");
+    }
 
-        MongoRepo::drop("test").await;
+    #[actix_web::test]
+    async fn test_non_latin_layout() {
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
+
+        let greek = json!({
+            "name": "greek",
+            "keys": [ ";ςερτυθιοπ", "ασδφγηξκλ΄", "ζχψωβνμ,./" ],
+        });
+        let req = test::TestRequest::post()
+            .uri("/layouts")
+            .set_json(&greek)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201, "Rows of 10 keys in 2-byte letters");
+
+        // The last key is "е" followed by a combining diaeresis, two chars but one key.
+        let russian = json!({
+            "name": "russian",
+            "keys": [ "йцукенгшщз", "фывапролдж", "ячсмитьбюе\u{308}" ],
+        });
+        let req = test::TestRequest::post()
+            .uri("/layouts")
+            .set_json(&russian)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 201, "Keys are graphemes");
+    }
+
+    #[actix_web::test]
+    async fn test_layout_geometry() {
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
+
+        let cases = [
+            (
+                json!(["qwertyuiop[]", "asdfghjkl;'", "zxcvbnm,./", " "]),
+                None,
+                201,
+            ),
+            (
+                json!(["qwert", "asdfg", "zxcvb"]),
+                Some(json!(["qaz", "ws"])),
+                201,
+            ),
+            (json!([]), None, 400),
+            (json!(["abc", ""]), None, 400),
+            (json!(["abc"]), Some(json!(["ad"])), 400),
+            (json!(["abc"]), Some(json!(["ab", "bc"])), 400),
+        ];
+        for (i, (keys, groups, status)) in cases.into_iter().enumerate() {
+            let req = test::TestRequest::post()
+                .uri("/layouts")
+                .set_json(json!({ "name": format!("layout{i}"), "keys": keys, "groups": groups }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{keys} {groups:?}");
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/layouts/layout1")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["groups"], json!(["qaz", "ws"]));
+    }
+
+    #[actix_web::test]
+    async fn test_keypad_layout() {
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
+
+        let keypad = json!(["123", "456", "789", "*0#"]);
+        let cases = [
+            (None, 201),
+            (Some(json!({ "2": "abc", "3": "def" })), 201),
+            (Some(json!({ "2": "abc", "A": "def" })), 400),
+            (Some(json!({ "2": "abc", "3": "cde" })), 400),
+        ];
+        for (i, (letters, status)) in cases.into_iter().enumerate() {
+            let req = test::TestRequest::post()
+                .uri("/layouts")
+                .set_json(json!({
+                    "name": format!("keypad{i}"),
+                    "kind": "keypad",
+                    "keys": keypad,
+                    "letters": letters,
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{letters:?}");
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/layouts/keypad0")
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(resp["data"]["kind"], "keypad");
+    }
+
+    #[actix_web::test]
+    async fn test_key_positions() {
+        let app = app_with(Repo::memory(&["en"]), Config::default(), "").await;
+
+        let key = |x: f64, width: f64| json!({ "x": x, "y": 0.5, "width": width, "height": 1 });
+        let cases = [
+            (json!({ "a": key(0.5, 1.0), "b": key(1.5, 1.0) }), 201),
+            (json!({ "a": key(0.5, 1.0), "z": key(1.5, 1.0) }), 400),
+            (json!({ "a": key(0.5, 0.0) }), 400),
+            (json!({}), 400),
+        ];
+        for (i, (positions, status)) in cases.into_iter().enumerate() {
+            let req = test::TestRequest::post()
+                .uri("/layouts")
+                .set_json(json!({
+                    "name": format!("positioned{i}"),
+                    "keys": ["ab"],
+                    "positions": positions,
+                }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{positions}");
+        }
     }
 }
@@ -1,7 +1,15 @@
-use actix_web::web;
+use actix_web::{web, HttpResponse};
+use serde_json::json;
 
+use crate::{
+    config::{Config, Language},
+    repositories::{LanguageModel, Repo},
+};
+
+pub mod artifacts;
 pub mod bigrams;
 pub mod examples;
+pub mod jobs;
 pub mod layouts;
 
 pub fn register_routes(cfg: &mut web::ServiceConfig) {
@@ -11,6 +19,77 @@ pub fn register_routes(cfg: &mut web::ServiceConfig) {
         scope
             .configure(examples::register_routes)
             .configure(bigrams::register_routes)
-            .configure(layouts::register_routes),
+            .configure(layouts::register_routes)
+            .configure(artifacts::register_routes)
+            .configure(jobs::register_routes),
     );
 }
+
+fn bad_request(error: String) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({ "error": error }))
+}
+
+/// Settings and model of the language a request names, or of the default language.
+fn find_language<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    code: Option<&str>,
+) -> Result<(&'a Language, &'a LanguageModel), String> {
+    config
+        .language(code)
+        .and_then(|language| Some((language, repo.model(&language.code)?)))
+        .ok_or_else(|| format!("Unknown language {:?}", code.unwrap_or_default()))
+}
+
+/// Like `find_language`, but identifies the language of `text` when the request names none.
+fn detect_language<'a>(
+    config: &'a Config,
+    repo: &'a Repo,
+    code: Option<&str>,
+    text: &str,
+) -> Result<(&'a Language, &'a LanguageModel), String> {
+    let code = code.or_else(|| config.identifier.identify(text));
+    find_language(config, repo, code)
+}
+
+/// App setup shared by the tests of the controllers.
+#[cfg(test)]
+pub mod fixtures {
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        test,
+    };
+
+    use super::*;
+
+    /// Every route, mounted without the `/api/v1` prefix, over `repo`, with `corpus` processed
+    /// first unless it is empty.
+    pub async fn app_with(
+        repo: Repo,
+        config: Config,
+        corpus: &str,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>
+    {
+        let app = test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(repo))
+                .app_data(web::Data::new(config))
+                .configure(examples::register_routes)
+                .configure(bigrams::register_routes)
+                .configure(layouts::register_routes)
+                .configure(artifacts::register_routes)
+                .configure(jobs::register_routes),
+        )
+        .await;
+
+        if !corpus.is_empty() {
+            let req = test::TestRequest::post()
+                .uri("/process_text")
+                .set_json(json!({ "text": corpus }))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success(), "Process the corpus");
+        }
+        app
+    }
+}
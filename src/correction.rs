@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    models::bigrams::{Prediction, PredictionKind},
+    repositories::{BigramStore, Context, StoreError},
+    smoothing::backoff,
+    spatial::TouchModel,
+    tokenizer::SENTENCE_END,
+};
+
+/// How much less likely a word gets for every edit it takes to type it as typed.
+const EDIT_PENALTY: f64 = 0.1;
+/// Weight of how frequent a word is overall where the context has never been followed by it.
+const BACKOFF: f64 = 0.4;
+/// Completions below which the letters typed are taken to be a slip and corrections are looked
+/// for too.
+pub const FEW_COMPLETIONS: usize = 3;
+
+/// Most edits a correction may be from the letters typed. Shorter words allow fewer, since
+/// nearly every short word is a couple of edits from any other.
+fn max_edits(typed: &[char]) -> f64 {
+    match typed.len() {
+        0..=2 => 0.0,
+        3..=5 => 1.0,
+        _ => 2.0,
+    }
+}
+
+/// Fewest edits that turn the typed letters into a prefix of a word read one letter at a time:
+/// inserting, deleting or swapping two neighbouring letters costs 1, and replacing one costs
+/// `substitution` of the letter typed and the one meant. Words with a common start share their
+/// steps, so a trie or FST can be walked with it, leaving every branch no word of which comes
+/// within `max_edits`.
+pub struct PrefixEdits<'a> {
+    typed: Vec<char>,
+    max_edits: f64,
+    substitution: &'a (dyn Fn(char, char) -> f64 + Sync),
+}
+
+/// Edits of `PrefixEdits` after some letters of a word.
+#[derive(Debug, Clone)]
+pub struct EditState {
+    /// Edits from every prefix of the typed letters to the letters read so far.
+    row: Vec<f64>,
+    /// The same without the last letter read, which swaps are measured from.
+    previous: Vec<f64>,
+    letter: Option<char>,
+    /// Fewest edits from the typed letters to the letters read so far or any prefix of them.
+    pub distance: f64,
+}
+
+impl<'a> PrefixEdits<'a> {
+    pub fn new(
+        typed: &str,
+        max_edits: f64,
+        substitution: &'a (dyn Fn(char, char) -> f64 + Sync),
+    ) -> Self {
+        Self {
+            typed: typed.chars().collect(),
+            max_edits,
+            substitution,
+        }
+    }
+
+    pub fn start(&self) -> EditState {
+        let row = (0..=self.typed.len())
+            .map(|i| i as f64)
+            .collect::<Vec<f64>>();
+        EditState {
+            distance: row[self.typed.len()],
+            row,
+            previous: vec![],
+            letter: None,
+        }
+    }
+
+    pub fn step(&self, state: &EditState, letter: char) -> EditState {
+        let typed = &self.typed;
+        let mut row = vec![state.row[0] + 1.0];
+        for i in 1..=typed.len() {
+            let replaced = if typed[i - 1] == letter {
+                0.0
+            } else {
+                (self.substitution)(typed[i - 1], letter)
+            };
+            let mut distance = (state.row[i] + 1.0)
+                .min(row[i - 1] + 1.0)
+                .min(state.row[i - 1] + replaced);
+            if i > 1 && state.letter == Some(typed[i - 1]) && typed[i - 2] == letter {
+                distance = distance.min(state.previous[i - 2] + 1.0);
+            }
+            row.push(distance);
+        }
+
+        EditState {
+            distance: state.distance.min(row[typed.len()]),
+            previous: state.row.clone(),
+            row,
+            letter: Some(letter),
+        }
+    }
+
+    /// Whether reading more letters may still bring the distance down within `max_edits`. Later
+    /// rows never go below the smallest edits of the last one, nor more than one below the
+    /// one before, which a swap reaches back to.
+    pub fn can_improve(&self, state: &EditState) -> bool {
+        let least = |row: &[f64]| row.iter().copied().fold(f64::INFINITY, f64::min);
+        let bound = least(&state.row).min(least(&state.previous) + 1.0);
+        bound < state.distance && bound <= self.max_edits
+    }
+
+    /// Whether words starting with the letters read so far are corrections: within
+    /// `max_edits`, but not completions of the letters typed.
+    pub fn is_correction(&self, state: &EditState) -> bool {
+        state.distance > 0.0 && state.distance <= self.max_edits
+    }
+
+    /// Whether any word starting with the letters read so far may be a correction.
+    pub fn can_match(&self, state: &EditState) -> bool {
+        self.is_correction(state) || self.can_improve(state)
+    }
+}
+
+/// Adds to the `completions` of `typed`, the normalized word being typed with the ambiguity
+/// `groups`, the vocabulary words a few slips away from it. Every candidate is weighed by how
+/// likely it is after the longest suffix of `context` it has followed, or by its overall
+/// frequency, backed off, and corrections by `EDIT_PENALTY` for every edit too, the nearer
+/// the keys the less. Completions keep their order, and all of them share the probability
+/// with the corrections in proportion to those weights.
+pub async fn add_corrections<S: BigramStore + ?Sized>(
+    store: &S,
+    context: &[&str],
+    typed: &str,
+    groups: &[String],
+    adjacency: &TouchModel,
+    completions: Vec<Prediction>,
+) -> Result<Vec<Prediction>, StoreError> {
+    let max_edits = max_edits(&typed.chars().collect::<Vec<char>>());
+    if max_edits == 0.0 {
+        return Ok(completions);
+    }
+
+    let substitution = |typed, meant| 1.0 - adjacency.likelihood(typed, meant);
+    let edits = PrefixEdits::new(typed, max_edits, &substitution);
+    let mut vocabulary = Context::Exact("");
+    let mut similar = store.find_similar(vocabulary, &edits).await?;
+    // Corpora ingested before unigrams were kept only have bigrams to go by.
+    if similar.is_empty() {
+        vocabulary = Context::Extending("");
+        similar = store.find_similar(vocabulary, &edits).await?;
+    }
+    similar.retain(|(word, _)| {
+        word.word != SENTENCE_END
+            && !completions
+                .iter()
+                .any(|completion| completion.word == word.word)
+    });
+    if similar.is_empty() {
+        return Ok(completions);
+    }
+
+    let candidates = completions
+        .iter()
+        .map(|completion| completion.word.as_str())
+        .chain(similar.iter().map(|(word, _)| word.word.as_str()))
+        .collect::<HashSet<&str>>();
+    let mut seen = HashMap::new();
+    for first in backoff(context) {
+        let counts = store.find_counts(Context::Exact(&first), None, &[]).await?;
+        for following in counts.words {
+            if following.count > 0 && candidates.contains(following.word.as_str()) {
+                let probability = following.count as f64 / counts.total as f64;
+                seen.entry(following.word).or_insert(probability);
+            }
+        }
+    }
+    let unigrams = store.find_counts(vocabulary, Some(typed), groups).await?;
+    let weight = |word: &str, count: u64| match seen.get(word) {
+        Some(probability) => *probability,
+        None => BACKOFF * count as f64 / unigrams.total.max(1) as f64,
+    };
+
+    let completed = completions
+        .iter()
+        .map(|completion| {
+            let count = unigrams
+                .words
+                .iter()
+                .find(|word| word.word == completion.word)
+                .map_or(0, |word| word.count);
+            weight(&completion.word, count)
+        })
+        .sum::<f64>();
+    let corrections = similar
+        .into_iter()
+        .map(|(word, distance)| Prediction {
+            probability: weight(&word.word, word.count) * EDIT_PENALTY.powf(distance),
+            word: word.word,
+            kind: PredictionKind::Correction,
+        })
+        .collect::<Vec<Prediction>>();
+    let total = completed
+        + corrections
+            .iter()
+            .map(|correction| correction.probability)
+            .sum::<f64>();
+    let scale = completions
+        .iter()
+        .map(|completion| completion.probability)
+        .sum::<f64>();
+    if total == 0.0 {
+        return Ok(completions);
+    }
+
+    let mut predictions = completions
+        .into_iter()
+        .map(|completion| Prediction {
+            probability: if scale > 0.0 {
+                completion.probability / scale * completed / total
+            } else {
+                0.0
+            },
+            ..completion
+        })
+        .chain(corrections.into_iter().map(|correction| Prediction {
+            probability: correction.probability / total,
+            ..correction
+        }))
+        .collect::<Vec<Prediction>>();
+    predictions.sort_by(|a, b| {
+        b.probability
+            .total_cmp(&a.probability)
+            .then_with(|| a.word.cmp(&b.word))
+    });
+    Ok(predictions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(typed: &str, word: &str) -> f64 {
+        read(&PrefixEdits::new(typed, 2.0, &|_, _| 1.0), word).distance
+    }
+
+    /// Edits of `word` after reading all of it, stopping early once it cannot be a correction.
+    fn read(edits: &PrefixEdits, word: &str) -> EditState {
+        let mut state = edits.start();
+        for letter in word.chars() {
+            if !edits.can_improve(&state) {
+                break;
+            }
+            state = edits.step(&state, letter);
+        }
+        state
+    }
+
+    #[test]
+    fn test_prefix_distance() {
+        assert_eq!(distance("hel", "hello"), 0.0);
+        assert_eq!(distance("hwl", "hello"), 1.0);
+        assert_eq!(distance("hlel", "hello"), 1.0);
+        assert_eq!(distance("hllo", "hello"), 1.0);
+        assert_eq!(distance("heello", "hello"), 1.0);
+        assert_eq!(distance("jwllo", "hello"), 2.0);
+
+        let edits = PrefixEdits::new("hrl", 1.0, &|_, _| 0.25);
+        assert_eq!(read(&edits, "hello").distance, 0.25);
+    }
+
+    #[test]
+    fn test_pruning() {
+        let edits = PrefixEdits::new("hello", 1.0, &|_, _| 1.0);
+
+        let state = "xy"
+            .chars()
+            .fold(edits.start(), |state, letter| edits.step(&state, letter));
+        assert!(
+            !edits.can_match(&state),
+            "No word starting with \"xy\" is near"
+        );
+
+        let state = "hel"
+            .chars()
+            .fold(edits.start(), |state, letter| edits.step(&state, letter));
+        assert!(edits.can_match(&state));
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    correction::{EditState, PrefixEdits},
+    models::bigrams::{BigramModel, ContextCounts, WordCount},
+    repositories::Context,
+    utils::{get_key_group, saturating_u32},
+};
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<char, Node>,
+    count: u64,
+    contexts: u64,
+}
+
+/// Words following one context, stored letter by letter so that typed prefixes can be walked
+/// with every letter their key may stand for.
+#[derive(Default)]
+struct Trie {
+    root: Node,
+    total: u64,
+    total_contexts: u64,
+    types: u64,
+}
+
+impl Trie {
+    fn count(&self, word: &str) -> u64 {
+        let mut node = &self.root;
+        for c in word.chars() {
+            match node.children.get(&c) {
+                Some(child) => node = child,
+                None => return 0,
+            }
+        }
+        node.count
+    }
+
+    fn add(&mut self, word: &str, count: u64, contexts: u64) {
+        let node = word.chars().fold(&mut self.root, |node, c| {
+            node.children.entry(c).or_default()
+        });
+        if node.count == 0 && count > 0 {
+            self.types += 1;
+        }
+        node.count += count;
+        node.contexts += contexts;
+        self.total += count;
+        self.total_contexts += contexts;
+    }
+
+    fn find_counts(&self, second: Option<&str>, groups: &[String]) -> ContextCounts {
+        let typed = second
+            .unwrap_or_default()
+            .chars()
+            .map(|letter| (letter, get_key_group(letter, groups)))
+            .collect::<Vec<(char, Option<String>)>>();
+
+        let mut words = vec![];
+        collect(&self.root, &typed, &mut String::new(), &mut words);
+
+        ContextCounts {
+            total: self.total,
+            total_contexts: self.total_contexts,
+            types: self.types,
+            words,
+        }
+    }
+}
+
+/// Gathers the words below `node` whose letters fit the remaining key `groups`.
+fn collect(
+    node: &Node,
+    groups: &[(char, Option<String>)],
+    word: &mut String,
+    words: &mut Vec<WordCount>,
+) {
+    let children = node.children.iter();
+
+    match groups.split_first() {
+        Some(((letter, group), rest)) => {
+            for (c, child) in children {
+                let allowed = match group {
+                    Some(chars) => chars.contains(*c),
+                    None => c == letter,
+                };
+                if allowed {
+                    word.push(*c);
+                    collect(child, rest, word, words);
+                    word.pop();
+                }
+            }
+        }
+        None => {
+            if node.count > 0 {
+                words.push(WordCount {
+                    word: word.clone(),
+                    count: node.count,
+                    contexts: node.contexts,
+                });
+            }
+            for (c, child) in children {
+                word.push(*c);
+                collect(child, groups, word, words);
+                word.pop();
+            }
+        }
+    }
+}
+
+/// Gathers the words below `node` that `edits` finds to be corrections, once reading `word` got
+/// it to `state`, leaving the branches where none can be.
+fn collect_similar(
+    node: &Node,
+    edits: &PrefixEdits<'_>,
+    state: EditState,
+    word: &mut String,
+    similar: &mut Vec<(WordCount, f64)>,
+) {
+    // Longer words are just as far, so the whole branch is in or out.
+    if !edits.can_improve(&state) {
+        if edits.is_correction(&state) {
+            let mut words = vec![];
+            collect(node, &[], word, &mut words);
+            similar.extend(words.into_iter().map(|word| (word, state.distance)));
+        }
+        return;
+    }
+
+    if node.count > 0 && edits.is_correction(&state) {
+        let found = WordCount {
+            word: word.clone(),
+            count: node.count,
+            contexts: node.contexts,
+        };
+        similar.push((found, state.distance));
+    }
+    for (c, child) in &node.children {
+        word.push(*c);
+        collect_similar(child, edits, edits.step(&state, *c), word, similar);
+        word.pop();
+    }
+}
+
+/// In-memory n-gram counts answering `find_counts` without scanning the whole corpus.
+#[derive(Default)]
+pub struct PrefixIndex {
+    /// Words following each exact context.
+    contexts: HashMap<String, Trie>,
+    /// Words following each context minus its first word, for continuation counts.
+    extensions: HashMap<String, Trie>,
+}
+
+impl PrefixIndex {
+    pub fn insert(&mut self, first: &str, second: &str, count: u64) {
+        let trie = self.contexts.entry(first.to_string()).or_default();
+        let new = trie.count(second) == 0;
+        trie.add(second, count, new as u64);
+
+        if !first.is_empty() {
+            let suffix = first.split_once(' ').map_or("", |(_, suffix)| suffix);
+            self.extensions
+                .entry(suffix.to_string())
+                .or_default()
+                .add(second, count, new as u64);
+        }
+    }
+
+    pub fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> ContextCounts {
+        let trie = match context {
+            Context::Exact(context) => self.contexts.get(context),
+            Context::Extending(suffix) => self.extensions.get(suffix),
+        };
+        trie.map(|trie| trie.find_counts(second, groups))
+            .unwrap_or_default()
+    }
+
+    /// Words following `context` that `edits` finds to be corrections, with their edits.
+    pub fn find_similar(
+        &self,
+        context: Context<'_>,
+        edits: &PrefixEdits<'_>,
+    ) -> Vec<(WordCount, f64)> {
+        let trie = match context {
+            Context::Exact(context) => self.contexts.get(context),
+            Context::Extending(suffix) => self.extensions.get(suffix),
+        };
+        let mut similar = vec![];
+        if let Some(trie) = trie {
+            let root = &trie.root;
+            collect_similar(root, edits, edits.start(), &mut String::new(), &mut similar);
+        }
+        similar
+    }
+
+    pub fn ngrams(&self) -> Vec<BigramModel> {
+        self.contexts
+            .iter()
+            .flat_map(|(first, trie)| {
+                trie.find_counts(None, &[])
+                    .words
+                    .into_iter()
+                    .map(|word| BigramModel {
+                        id: None,
+                        first: first.clone(),
+                        second: word.word,
+                        count: saturating_u32(word.count),
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::legacy_groups;
+
+    #[test]
+    fn test_find_counts() {
+        let groups = legacy_groups(&["qwertyuiop", "asdfghjkl;", "zxcvbnm,./"].map(String::from));
+
+        let mut index = PrefixIndex::default();
+        index.insert("the", "cat", 2);
+        index.insert("the", "cap", 1);
+        index.insert("the", "dog", 1);
+        index.insert("a", "cat", 1);
+        index.insert("on the", "cat", 1);
+
+        let counts = index.find_counts(Context::Exact("the"), Some("ca"), &groups);
+        let mut words = counts
+            .words
+            .iter()
+            .map(|word| (word.word.as_str(), word.count))
+            .collect::<Vec<(&str, u64)>>();
+        words.sort();
+        assert_eq!(words, vec![("cap", 1), ("cat", 2)]);
+        assert_eq!((counts.total, counts.types), (4, 3));
+
+        // "d" shares its key with "e" and "c" on this layout.
+        let counts = index.find_counts(Context::Exact("the"), Some("d"), &groups);
+        assert_eq!(counts.words.len(), 3);
+
+        let counts = index.find_counts(Context::Extending(""), Some("cat"), &groups);
+        assert_eq!(counts.words[0].contexts, 2);
+        assert_eq!(counts.words[0].count, 3);
+
+        let counts = index.find_counts(Context::Extending("the"), None, &groups);
+        assert_eq!(counts.words[0].contexts, 1);
+
+        index.insert("a", "cat", u32::MAX as u64);
+        let ngram = index
+            .ngrams()
+            .into_iter()
+            .find(|ngram| ngram.first == "a")
+            .unwrap();
+        assert_eq!(ngram.count, u32::MAX, "Counts past u32 saturate");
+    }
+
+    #[test]
+    fn test_find_similar() {
+        let mut index = PrefixIndex::default();
+        for word in ["cat", "cart", "cattle", "dog", "cab"] {
+            index.insert("", word, 1);
+        }
+
+        let edits = PrefixEdits::new("cta", 1.0, &|_, _| 1.0);
+        let mut similar = index
+            .find_similar(Context::Exact(""), &edits)
+            .into_iter()
+            .map(|(word, distance)| (word.word, distance))
+            .collect::<Vec<(String, f64)>>();
+        similar.sort_by(|a, b| a.0.cmp(&b.0));
+        let expected = [("cab", 1.0), ("cart", 1.0), ("cat", 1.0), ("cattle", 1.0)];
+        assert_eq!(
+            similar,
+            expected.map(|(word, distance)| (word.to_string(), distance))
+        );
+    }
+}
@@ -0,0 +1,405 @@
+use std::{collections::HashMap, io::Write, sync::Arc};
+
+use flate2::write::GzDecoder;
+use log::{error, warn};
+
+use crate::{
+    config::{Config, Language},
+    models::{
+        bigrams::{BigramModel, SurfaceForm},
+        jobs::{JobModel, JobStatus},
+    },
+    normalization::Normalizer,
+    repositories::{BigramStore, Repo, StoreError},
+    tokenizer::{Tokenizer, SENTENCE_END, SENTENCE_START},
+    utils::is_capitalized,
+};
+
+/// Words a background job counts between two writes and progress updates.
+const JOB_BATCH_WORDS: usize = 10_000;
+/// Bytes of a document without whitespace held back before they are counted anyway.
+const MAX_PENDING: usize = 64 * 1024;
+
+/// Aggregates the n-gram counts of one or more documents before they are written in bulk.
+/// Text may arrive in arbitrary pieces, n-grams only stop at sentence and document boundaries.
+/// Every sentence is counted between `SENTENCE_START` and `SENTENCE_END` markers.
+pub struct NgramCounter {
+    order: usize,
+    tokenizer: Tokenizer,
+    normalizer: Normalizer,
+    counts: HashMap<(String, String), u32>,
+    /// Times each normalized word was written in each surface form.
+    forms: HashMap<(String, String), u32>,
+    /// Last words of the current sentence, the context of the next one.
+    history: Vec<String>,
+    /// Bytes of the current document that may end in the middle of a word.
+    pending: Vec<u8>,
+    /// Words counted so far.
+    pub word_count: usize,
+    /// Consecutive word pairs counted so far.
+    pub bigram_count: usize,
+    /// N-gram occurrences counted so far.
+    pub ngram_count: usize,
+}
+
+impl NgramCounter {
+    /// Counts text of `language` with the deployment's n-gram order and tokenizer.
+    pub fn new(config: &Config, language: &Language) -> Self {
+        Self {
+            order: config.ngram_order,
+            tokenizer: config.tokenizer.clone(),
+            normalizer: language.normalizer.clone(),
+            counts: HashMap::new(),
+            forms: HashMap::new(),
+            history: vec![SENTENCE_START.to_string()],
+            pending: vec![],
+            word_count: 0,
+            bigram_count: 0,
+            ngram_count: 0,
+        }
+    }
+
+    /// Counts `text` as a whole document.
+    pub fn add_text(&mut self, text: &str) {
+        self.feed(text.as_bytes());
+        self.end_document();
+    }
+
+    /// Counts the complete words of the next piece of the current document, keeping a
+    /// trailing partial word (or UTF-8 sequence) until more bytes arrive. Text that goes on for
+    /// `MAX_PENDING` bytes without whitespace, as Chinese does, is counted at the last whole
+    /// character instead.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+
+        let mut end = None;
+        let mut offset = 0;
+        let mut whole = 0;
+        for chunk in self.pending.utf8_chunks() {
+            let valid = chunk.valid();
+            if let Some((i, space)) = valid.char_indices().rfind(|(_, c)| c.is_whitespace()) {
+                end = Some(offset + i + space.len_utf8());
+            }
+            offset += valid.len();
+            whole = offset;
+            offset += chunk.invalid().len();
+        }
+
+        if let Some(end) = end.or((whole >= MAX_PENDING).then_some(whole)) {
+            let rest = self.pending.split_off(end);
+            let complete = std::mem::replace(&mut self.pending, rest);
+            self.count(&String::from_utf8_lossy(&complete));
+        }
+    }
+
+    /// Counts whatever is left of the current document, ending its last sentence.
+    pub fn end_document(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        self.count(&String::from_utf8_lossy(&pending));
+        self.end_sentence();
+    }
+
+    fn count(&mut self, text: &str) {
+        for (_, word) in self.tokenizer.words(text) {
+            if word == SENTENCE_END {
+                self.end_sentence();
+                continue;
+            }
+
+            if self
+                .history
+                .last()
+                .is_some_and(|last| last != SENTENCE_START)
+            {
+                self.bigram_count += 1;
+            }
+            let normalized = self.normalizer.normalize(word);
+            let mut form = self.normalizer.surface(word);
+            // A capital at the start of a sentence says nothing about how the word is written.
+            if self.history == [SENTENCE_START] && is_capitalized(&form) {
+                form = form.to_lowercase();
+            }
+            *self.forms.entry((normalized.clone(), form)).or_insert(0) += 1;
+            self.count_word(normalized);
+            self.word_count += 1;
+        }
+    }
+
+    fn end_sentence(&mut self) {
+        if self.history != [SENTENCE_START] {
+            self.count_word(SENTENCE_END.to_string());
+            self.history = vec![SENTENCE_START.to_string()];
+        }
+    }
+
+    fn count_word(&mut self, word: String) {
+        for n in 1..=self.order.min(self.history.len() + 1) {
+            let first = self.history[self.history.len() + 1 - n..].join(" ");
+            *self.counts.entry((first, word.clone())).or_insert(0) += 1;
+            self.ngram_count += 1;
+        }
+
+        self.history.push(word);
+        if self.history.len() >= self.order {
+            self.history.remove(0);
+        }
+    }
+
+    /// Number of distinct n-grams waiting to be written.
+    pub fn pending_ngrams(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Writes the n-grams and surface forms counted since the last flush.
+    pub async fn flush(&mut self, store: &dyn BigramStore) -> Result<(), StoreError> {
+        store.upsert_many(&self.take_ngrams()).await?;
+
+        let forms = self
+            .forms
+            .drain()
+            .map(|((word, form), count)| SurfaceForm { word, form, count })
+            .collect::<Vec<SurfaceForm>>();
+        store.upsert_forms(&forms).await
+    }
+
+    /// Distinct n-grams counted since the last call, ready for `BigramStore::upsert_many`.
+    pub fn take_ngrams(&mut self) -> Vec<BigramModel> {
+        self.counts
+            .drain()
+            .map(|((first, second), count)| BigramModel {
+                id: None,
+                first,
+                second,
+                count,
+            })
+            .collect()
+    }
+}
+
+/// Turns the bytes of an uploaded corpus file into plain text as they stream in.
+pub enum CorpusDecoder {
+    Plain,
+    Gzip(Box<GzDecoder<Vec<u8>>>),
+}
+
+impl CorpusDecoder {
+    /// Picks the decoder from the file name, `None` for unsupported files.
+    pub fn for_file(name: &str) -> Option<Self> {
+        if name.ends_with(".txt.gz") {
+            Some(CorpusDecoder::Gzip(Box::new(GzDecoder::new(vec![]))))
+        } else if name.ends_with(".txt") {
+            Some(CorpusDecoder::Plain)
+        } else {
+            None
+        }
+    }
+
+    pub fn decode(&mut self, chunk: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            CorpusDecoder::Plain => Ok(chunk.to_vec()),
+            CorpusDecoder::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    pub fn finish(self) -> std::io::Result<Vec<u8>> {
+        match self {
+            CorpusDecoder::Plain => Ok(vec![]),
+            CorpusDecoder::Gzip(decoder) => decoder.finish(),
+        }
+    }
+}
+
+/// Counts `text` into `bigrams` in batches for the stored `job`, recording progress after each
+/// write. Stops early once the job is cancelled; n-grams written before that are kept.
+pub async fn run_job(
+    repo: Repo,
+    bigrams: Arc<dyn BigramStore>,
+    mut job: JobModel,
+    text: String,
+    mut counter: NgramCounter,
+) {
+    job.status = JobStatus::Running;
+    if !save_job(&repo, &job).await {
+        return;
+    }
+
+    let cuts = text
+        .match_indices(char::is_whitespace)
+        .map(|(i, whitespace)| i + whitespace.len())
+        .skip(JOB_BATCH_WORDS - 1)
+        .step_by(JOB_BATCH_WORDS)
+        .chain([text.len()]);
+    let mut start = 0;
+    for end in cuts {
+        counter.feed(&text.as_bytes()[start..end]);
+        if end == text.len() {
+            counter.end_document();
+        }
+        start = end;
+
+        if let Err(err) = counter.flush(bigrams.as_ref()).await {
+            job.errors.push(err.to_string());
+            job.status = JobStatus::Failed;
+            save_job(&repo, &job).await;
+            return;
+        }
+        job.words_processed = counter.word_count as u64;
+        job.bigrams_written = counter.bigram_count as u64;
+        if !save_job(&repo, &job).await {
+            return;
+        }
+    }
+
+    job.status = JobStatus::Completed;
+    save_job(&repo, &job).await;
+}
+
+/// Stores the job's progress, returning whether it should keep going.
+async fn save_job(repo: &Repo, job: &JobModel) -> bool {
+    match repo.jobs.update(job).await {
+        Ok(updated) => updated,
+        Err(err) => {
+            error!("Failed to save ingestion job {:?}: {err}", job.id);
+            false
+        }
+    }
+}
+
+/// Marks the jobs a previous run of the `instance` left unfinished as failed, since their text is
+/// not kept. Jobs of other instances sharing the database may still be running.
+pub async fn interrupt_jobs(repo: &Repo, instance: &str) {
+    let jobs = match repo.jobs.find_unfinished(instance).await {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            error!("Failed to load unfinished ingestion jobs: {err}");
+            return;
+        }
+    };
+
+    for mut job in jobs {
+        warn!("Ingestion job {:?} was interrupted by a restart", job.id);
+        job.status = JobStatus::Failed;
+        job.errors
+            .push("Interrupted by a server restart".to_string());
+        save_job(repo, &job).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_matches_add_text() {
+        let config = Config::default();
+        let text = "El niño canta y la niña baila con el niño";
+
+        let mut whole = NgramCounter::new(&config, &config.languages[0]);
+        whole.add_text(text);
+
+        // Split inside words and inside the two bytes of "ñ".
+        let mut pieces = NgramCounter::new(&config, &config.languages[0]);
+        for chunk in text.as_bytes().chunks(3) {
+            pieces.feed(chunk);
+        }
+        pieces.end_document();
+
+        let sorted = |counter: &mut NgramCounter| {
+            let mut ngrams = counter
+                .take_ngrams()
+                .into_iter()
+                .map(|ngram| (ngram.first, ngram.second, ngram.count))
+                .collect::<Vec<(String, String, u32)>>();
+            ngrams.sort();
+            ngrams
+        };
+        assert_eq!(sorted(&mut pieces), sorted(&mut whole));
+        assert_eq!(pieces.bigram_count, 9);
+    }
+
+    #[test]
+    fn test_feed_cuts_at_any_whitespace() {
+        let config = Config::default();
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+
+        counter.feed("uno\u{3000}dos\u{a0}tr".as_bytes());
+        assert_eq!(counter.word_count, 2);
+        assert_eq!(counter.pending, b"tr");
+
+        // Without whitespace, text is counted once enough of it piles up, never inside "字".
+        for chunk in "字".repeat(MAX_PENDING).as_bytes().chunks(1000) {
+            counter.feed(chunk);
+            assert!(counter.pending.len() < MAX_PENDING + 1000);
+        }
+        assert!(std::str::from_utf8(&counter.pending).is_ok_and(|rest| rest.ends_with('字')));
+    }
+
+    #[actix_web::test]
+    async fn test_interrupt_own_jobs() {
+        let repo = Repo::memory(&["en"]);
+        let mut ids = vec![];
+        for owner in ["restarted", "other"] {
+            let job = JobModel {
+                status: JobStatus::Running,
+                owner: Some(owner.to_string()),
+                ..JobModel::default()
+            };
+            ids.push(repo.jobs.create(&job).await.unwrap());
+        }
+
+        interrupt_jobs(&repo, "restarted").await;
+        let status = |job: Option<JobModel>| job.unwrap().status;
+        assert_eq!(
+            status(repo.jobs.find(ids[0]).await.unwrap()),
+            JobStatus::Failed
+        );
+        assert_eq!(
+            status(repo.jobs.find(ids[1]).await.unwrap()),
+            JobStatus::Running
+        );
+    }
+
+    #[test]
+    fn test_documents_do_not_share_ngrams() {
+        let config = Config::default();
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+        counter.add_text("hello world");
+        counter.add_text("again");
+
+        let ngrams = counter.take_ngrams();
+        assert!(!ngrams
+            .iter()
+            .any(|ngram| ngram.second == "again" && ngram.first.contains("world")));
+        assert_eq!(counter.bigram_count, 1);
+    }
+
+    #[test]
+    fn test_sentence_markers() {
+        let config = Config::default();
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+        counter.add_text("The end. Start again!");
+
+        let ngrams = counter
+            .take_ngrams()
+            .into_iter()
+            .map(|ngram| (ngram.first, ngram.second))
+            .collect::<Vec<(String, String)>>();
+        for ngram in [
+            ("<s>", "start"),
+            ("the end", "</s>"),
+            ("start again", "</s>"),
+        ] {
+            assert!(
+                ngrams.contains(&(ngram.0.to_string(), ngram.1.to_string())),
+                "{ngram:?}"
+            );
+        }
+        assert!(!ngrams
+            .iter()
+            .any(|(first, second)| first.ends_with("end") && second != "</s>"));
+        assert_eq!(counter.bigram_count, 2);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::{HashMap, HashSet};
+
+use log::warn;
+
+/// Sample texts the identifier knows languages by when nothing else is configured.
+const BUILTIN: &[(&str, &str)] = &[
+    ("de", include_str!("language_profiles/de.txt")),
+    ("en", include_str!("language_profiles/en.txt")),
+    ("es", include_str!("language_profiles/es.txt")),
+    ("fr", include_str!("language_profiles/fr.txt")),
+    ("it", include_str!("language_profiles/it.txt")),
+    ("pt", include_str!("language_profiles/pt.txt")),
+];
+
+/// Characters of a document looked at to identify its language.
+const SAMPLE_CHARS: usize = 4096;
+/// Fewest letters a text needs for its language to be told; shorter texts look alike in most.
+const MIN_LETTERS: usize = 8;
+/// Least lead in probability the most likely language needs over the next one to be picked.
+const MIN_MARGIN: f64 = 0.7;
+
+/// Sequences of one to three letters of `text`, with every word padded by a space on each side
+/// so that word starts and ends count too.
+fn ngrams(text: &str) -> Vec<String> {
+    let mut ngrams = vec![];
+    let text = text.chars().take(SAMPLE_CHARS).collect::<String>();
+    for word in text.split(|c: char| !c.is_alphabetic()) {
+        if word.is_empty() {
+            continue;
+        }
+        let chars = [' ']
+            .into_iter()
+            .chain(word.to_lowercase().chars())
+            .chain([' '])
+            .collect::<Vec<char>>();
+        for n in 1..=3 {
+            ngrams.extend(chars.windows(n).map(|window| window.iter().collect()));
+        }
+    }
+    ngrams
+}
+
+#[derive(Debug, Clone, Default)]
+struct Profile {
+    counts: HashMap<String, u64>,
+    total: u64,
+}
+
+/// Offline language identification with a naive Bayes model over letter n-grams.
+#[derive(Debug, Clone, Default)]
+pub struct LanguageIdentifier {
+    profiles: Vec<(String, Profile)>,
+    /// Distinct n-grams across every profile, for add-one smoothing.
+    vocabulary: u64,
+}
+
+impl LanguageIdentifier {
+    /// Learns each of `languages` that has a built-in sample text, plus the text of
+    /// `<code>.txt` in the `LANGUAGE_PROFILES` directory when that is set.
+    pub fn from_env(languages: &[&str]) -> Self {
+        let mut identifier = Self::builtin(languages);
+        if let Ok(directory) = std::env::var("LANGUAGE_PROFILES") {
+            for language in languages {
+                let path = std::path::Path::new(&directory).join(format!("{language}.txt"));
+                if let Ok(text) = std::fs::read_to_string(path) {
+                    identifier.learn(language, &text);
+                }
+            }
+        }
+
+        for language in languages {
+            if !identifier.profiles.iter().any(|(code, _)| code == language) {
+                warn!("No language profile for {language:?}, it will never be identified");
+            }
+        }
+        identifier
+    }
+
+    /// Learns each of `languages` that has a built-in sample text.
+    pub fn builtin(languages: &[&str]) -> Self {
+        let mut identifier = Self::default();
+        for (language, text) in BUILTIN {
+            if languages.contains(language) {
+                identifier.learn(language, text);
+            }
+        }
+        identifier
+    }
+
+    /// Adds the n-grams of `text` to the profile of `language`.
+    pub fn learn(&mut self, language: &str, text: &str) {
+        let position = match self.profiles.iter().position(|(code, _)| code == language) {
+            Some(position) => position,
+            None => {
+                self.profiles
+                    .push((language.to_string(), Profile::default()));
+                self.profiles.len() - 1
+            }
+        };
+
+        let profile = &mut self.profiles[position].1;
+        for ngram in ngrams(text) {
+            *profile.counts.entry(ngram).or_default() += 1;
+            profile.total += 1;
+        }
+
+        self.vocabulary = self
+            .profiles
+            .iter()
+            .flat_map(|(_, profile)| profile.counts.keys())
+            .collect::<HashSet<&String>>()
+            .len() as u64;
+    }
+
+    /// Probability of each known language given `text`, most likely first. Empty when the text
+    /// has no letters or no language is known.
+    pub fn scores(&self, text: &str) -> Vec<(&str, f64)> {
+        let ngrams = ngrams(text);
+        if ngrams.is_empty() {
+            return vec![];
+        }
+
+        let mut scores = self
+            .profiles
+            .iter()
+            .map(|(language, profile)| {
+                let denominator = (profile.total + self.vocabulary) as f64;
+                let log_probability = ngrams
+                    .iter()
+                    .map(|ngram| {
+                        let count = profile.counts.get(ngram).copied().unwrap_or(0);
+                        ((count + 1) as f64 / denominator).ln()
+                    })
+                    .sum::<f64>();
+                (language.as_str(), log_probability)
+            })
+            .collect::<Vec<(&str, f64)>>();
+
+        let best = scores
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let sum = scores
+            .iter()
+            .map(|(_, score)| (score - best).exp())
+            .sum::<f64>();
+        for (_, score) in scores.iter_mut() {
+            *score = (*score - best).exp() / sum;
+        }
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores
+    }
+
+    /// The most likely language of `text`, if it can tell. It cannot with fewer than two known
+    /// languages, since it would then pick the only one for any text, nor for texts shorter than
+    /// `MIN_LETTERS` or nearly as likely in another language.
+    pub fn identify(&self, text: &str) -> Option<&str> {
+        if self.profiles.len() < 2
+            || text.chars().filter(|c| c.is_alphabetic()).count() < MIN_LETTERS
+        {
+            return None;
+        }
+        match self.scores(text)[..] {
+            [(language, best), (_, next), ..] if best - next >= MIN_MARGIN => Some(language),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identify() {
+        let identifier = LanguageIdentifier::builtin(&["de", "en", "es", "fr", "it", "pt"]);
+
+        assert_eq!(identifier.identify("the weather is nice today"), Some("en"));
+        assert_eq!(identifier.identify("el perro come en la casa"), Some("es"));
+        assert_eq!(identifier.identify("je voudrais un café"), Some("fr"));
+        assert_eq!(identifier.identify("wir gehen nach Hause"), Some("de"));
+        assert_eq!(identifier.identify("não sei o que fazer"), Some("pt"));
+        assert_eq!(identifier.identify("la città è molto bella"), Some("it"));
+        assert_eq!(identifier.identify("42 !"), None);
+    }
+
+    #[test]
+    fn test_too_ambiguous() {
+        let identifier = LanguageIdentifier::builtin(&["de", "en", "es", "fr", "it", "pt"]);
+
+        assert_eq!(identifier.identify("a"), None);
+        assert_eq!(identifier.identify("la casa"), None);
+        // Spanish, Italian and Portuguese alike.
+        assert_eq!(identifier.identify("casa grande"), None);
+    }
+
+    #[test]
+    fn test_only_configured_languages() {
+        let identifier = LanguageIdentifier::builtin(&["en", "xx"]);
+
+        let scores = identifier.scores("el perro come en la casa");
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores[0].0, "en");
+        assert!((scores[0].1 - 1.0).abs() < 1e-9);
+        assert_eq!(identifier.identify("el perro come en la casa"), None);
+    }
+}
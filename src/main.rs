@@ -2,10 +2,20 @@ use actix_cors::Cors;
 use actix_web::{middleware, web, App, HttpServer};
 use log::info;
 
+mod artifact;
+mod config;
 mod controllers;
+mod correction;
 mod handlers;
+mod index;
+mod ingestion;
+mod language_id;
 mod models;
+mod normalization;
 mod repositories;
+mod smoothing;
+mod spatial;
+mod tokenizer;
 mod utils;
 
 #[actix_web::main]
@@ -19,8 +29,9 @@ async fn main() -> std::io::Result<()> {
         .expect("PORT must be a number");
     let front_url = std::env::var("FRONT_URL").expect("FRONT_URL must be set");
 
-    let db_name = std::env::var("MONGO_DB").expect("MONGO_DB must be set");
-    let repo = repositories::MongoRepo::init(&db_name).await;
+    let config = config::Config::from_env();
+    let repo = repositories::Repo::init(&config.language_codes()).await;
+    ingestion::interrupt_jobs(&repo, &config.instance).await;
 
     info!("Starting server on {bind_address}:{port}");
     HttpServer::new(move || {
@@ -34,6 +45,7 @@ async fn main() -> std::io::Result<()> {
             .wrap(middleware::NormalizePath::trim())
             .wrap(middleware::Logger::default())
             .wrap(cors)
+            .app_data(web::Data::new(config.clone()))
             .app_data(web::Data::new(repo.clone()))
             .configure(controllers::register_routes)
             .route("/", web::get().to(handlers::index))
@@ -1,27 +1,114 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::smoothing::Smoothing;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BigramModel {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<mongodb::bson::oid::ObjectId>,
+    /// Context words separated by single spaces; empty for unigrams, one word for bigrams, ...
+    /// Sentences start with the `<s>` marker as context.
     pub first: String,
+    /// Word that followed the context, or `</s>` where the sentence ended.
     pub second: String,
     pub count: u32,
 }
 
+/// How often a normalized word was written as `form`, e.g. "cancion" as "canción".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceForm {
+    pub word: String,
+    pub form: String,
+    pub count: u32,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProcessTextRequest {
     pub text: String,
+    /// Code of the language the text is in; the deployment's default language when unset.
+    pub language: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PredictRequest {
     pub text: String,
     pub layout: String,
+    /// Code of the language to predict in; the deployment's default language when unset.
+    pub language: Option<String>,
+    /// Overrides the deployment's `SMOOTHING` for this request.
+    pub smoothing: Option<Smoothing>,
+}
+
+/// Where a finger was on the layout at time `t`, in the unit of the layout's key positions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GesturePoint {
+    pub x: f64,
+    pub y: f64,
+    pub t: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SwipeRequest {
+    /// Text before the swiped word, the context it is predicted in.
+    pub text: String,
+    /// Layout with key positions the gesture was drawn on.
+    pub layout: String,
+    pub points: Vec<GesturePoint>,
+    /// Code of the language to predict in; the deployment's default language when unset.
+    pub language: Option<String>,
+    /// Overrides the deployment's `SMOOTHING` for this request.
+    pub smoothing: Option<Smoothing>,
+}
+
+/// Selects the language of endpoints without a JSON body.
+#[derive(Debug, Deserialize)]
+pub struct LanguageQuery {
+    pub language: Option<String>,
+}
+
+/// How likely a key, or a group of keys, is to be pressed next.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KeyProbability {
+    pub key: String,
+    pub probability: f64,
+}
+
+/// Whether a predicted word goes on from what was typed or corrects it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PredictionKind {
+    #[default]
+    Completion,
+    Correction,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Prediction {
     pub word: String,
     pub probability: f64,
+    #[serde(rename = "type", default)]
+    pub kind: PredictionKind,
+}
+
+/// Occurrences of one word after the n-grams selected by a `Context`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WordCount {
+    pub word: String,
+    /// Sum of the n-gram counts.
+    pub count: u64,
+    /// Number of distinct contexts the word followed.
+    pub contexts: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ContextCounts {
+    /// Sum of `count` over every word.
+    pub total: u64,
+    /// Sum of `contexts` over every word.
+    pub total_contexts: u64,
+    /// Number of distinct words.
+    pub types: u64,
+    /// Words that matched the typed prefix.
+    #[serde(default)]
+    pub words: Vec<WordCount>,
 }
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+/// Background ingestion of one `ProcessTextRequest`, with its progress so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobModel {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<mongodb::bson::oid::ObjectId>,
+    pub status: JobStatus,
+    /// Code of the language the text is counted in.
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Instance running the job, the only one that can tell it was interrupted.
+    #[serde(default)]
+    pub owner: Option<String>,
+    /// Words whose n-grams have been written.
+    pub words_processed: u64,
+    pub bigrams_written: u64,
+    pub errors: Vec<String>,
+}
+
+impl Default for JobModel {
+    fn default() -> Self {
+        Self {
+            id: None,
+            status: JobStatus::Queued,
+            language: None,
+            owner: None,
+            words_processed: 0,
+            bigrams_written: 0,
+            errors: vec![],
+        }
+    }
+}
@@ -1,9 +1,216 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    spatial::TouchModel,
+    utils::{keypad_letters, legacy_groups, row_keys, spell_keys},
+};
+
+/// Keypad keys that end the word being typed when they type no letters, like a space.
+const WORD_BREAK_KEYS: [&str; 2] = ["0", "#"];
+
+/// How the keys of a layout relate to the letters they type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutKind {
+    /// Every key types one letter, and the keys of a group may stand for each other.
+    #[default]
+    Keyboard,
+    /// Every key stands for several letters, like the digits of a phone keypad. Words are typed
+    /// as key sequences such as "4663".
+    Keypad,
+}
+
+/// Where a key is drawn, in any unit as long as every key of the layout uses the same one.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyPosition {
+    /// Horizontal position of the key's center.
+    pub x: f64,
+    /// Vertical position of the key's center.
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LayoutModel {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub id: Option<mongodb::bson::oid::ObjectId>,
     pub name: Option<String>,
+    #[serde(default)]
+    pub kind: LayoutKind,
+    /// Rows of keys, each key one grapheme. Rows may have any number of keys.
     pub keys: Vec<String>,
+    /// Sets of keys one press can stand for, each written as its keys one after another. Layouts
+    /// without them or `positions` get the groups of the original reduced keyboard, see
+    /// `legacy_groups`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<String>>,
+    /// Letters each key of a keypad stands for, such as "abc" for "2". Keypads without them
+    /// get those of phone keypads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub letters: Option<BTreeMap<String, String>>,
+    /// Where each key is. Keyboards with positions match typed letters with the keys around
+    /// them too, see `TouchModel`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub positions: Option<BTreeMap<String, KeyPosition>>,
+}
+
+impl LayoutModel {
+    /// The ambiguity groups typed letters are matched with.
+    pub fn key_groups(&self) -> Vec<String> {
+        match &self.groups {
+            Some(groups) => groups.clone(),
+            None if self.positions.is_some() => vec![],
+            None => legacy_groups(&self.keys),
+        }
+    }
+
+    /// Where each key is: its `positions`, or else a grid of unit keys with every row under the
+    /// one before.
+    pub fn key_positions(&self) -> BTreeMap<String, KeyPosition> {
+        if let Some(positions) = &self.positions {
+            return positions.clone();
+        }
+
+        let mut positions = BTreeMap::new();
+        for (row, keys) in self.keys.iter().enumerate() {
+            for (column, key) in row_keys(keys).into_iter().enumerate() {
+                positions.entry(key.to_string()).or_insert(KeyPosition {
+                    x: column as f64 + 0.5,
+                    y: row as f64 + 0.5,
+                    width: 1.0,
+                    height: 1.0,
+                });
+            }
+        }
+        positions
+    }
+
+    /// How near keys are to each other, from `key_positions`, for telling likely slips apart.
+    pub fn adjacency(&self) -> TouchModel {
+        TouchModel::new(self.key_positions(), self.key_groups())
+    }
+
+    /// The touch model of keyboards with key positions.
+    pub fn touch_model(&self) -> Option<TouchModel> {
+        match (self.kind, &self.positions) {
+            (LayoutKind::Keyboard, Some(positions)) => {
+                Some(TouchModel::new(positions.clone(), self.key_groups()))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn key_letters(&self) -> BTreeMap<String, String> {
+        self.letters.clone().unwrap_or_else(keypad_letters)
+    }
+
+    /// Every key of the layout once, in order, with the letters it types: its `key_letters` on a
+    /// keypad, and itself on a keyboard.
+    pub fn typed_letters(&self) -> Vec<(String, String)> {
+        let letters = self.key_letters();
+        let mut keys = Vec::<(String, String)>::new();
+        for key in self.keys.iter().flat_map(|row| row_keys(row)) {
+            if keys.iter().any(|(seen, _)| seen == key) {
+                continue;
+            }
+            let typed = match self.kind {
+                LayoutKind::Keypad => letters.get(key).cloned().unwrap_or_default(),
+                LayoutKind::Keyboard => key.to_string(),
+            };
+            keys.push((key.to_string(), typed));
+        }
+        keys
+    }
+
+    /// Splits `text` typed on a keypad into the text before the key sequence at its end and
+    /// that sequence, if it ends in one. Keys that type no letters are left out of the sequence,
+    /// like the "1" and "*" of phones, except "0" and "#", which end a word as a space does.
+    pub fn split_sequence(&self, text: &str) -> (String, Option<String>) {
+        let start = text.rfind(char::is_whitespace).map_or(0, |space| {
+            space + text[space..].chars().next().unwrap().len_utf8()
+        });
+        let (before, sequence) = text.split_at(start);
+
+        let keys = self
+            .keys
+            .iter()
+            .flat_map(|row| row_keys(row))
+            .collect::<Vec<&str>>();
+        if self.kind != LayoutKind::Keypad
+            || sequence.is_empty()
+            || !row_keys(sequence).iter().all(|key| keys.contains(key))
+        {
+            return (text.to_string(), None);
+        }
+
+        let letters = self.key_letters();
+        let mut words = vec![String::new()];
+        for key in row_keys(sequence) {
+            if letters.get(key).is_some_and(|letters| !letters.is_empty()) {
+                words.last_mut().unwrap().push_str(key);
+            } else if WORD_BREAK_KEYS.contains(&key) {
+                words.push(String::new());
+            }
+        }
+        let sequence = words.pop().filter(|sequence| !sequence.is_empty());
+        let mut text = before.to_string();
+        for word in words {
+            text.push_str(&word);
+            text.push(' ');
+        }
+        (text, sequence)
+    }
+
+    /// The letters a key sequence is matched as and their groups, see `spell_keys`.
+    pub fn spell(&self, sequence: &str) -> Result<(String, Vec<String>), String> {
+        spell_keys(sequence, &self.key_letters())
+    }
+}
+
+/// Layouts shared by the tests of the modules that predict with them.
+#[cfg(test)]
+pub mod fixtures {
+    use super::*;
+
+    pub fn layout(name: &str, kind: LayoutKind, rows: &[&str]) -> LayoutModel {
+        LayoutModel {
+            id: None,
+            name: Some(name.to_string()),
+            kind,
+            keys: rows.iter().map(|row| row.to_string()).collect(),
+            groups: None,
+            letters: None,
+            positions: None,
+        }
+    }
+
+    /// The reduced QWERTY keyboard of layouts without groups, see `legacy_groups`.
+    pub fn qwerty() -> LayoutModel {
+        let rows = ["qwertyuiop", "asdfghjkl;", "zxcvbnm,./"];
+        layout("qwerty", LayoutKind::Keyboard, &rows)
+    }
+
+    /// QWERTY letters as unit keys, every row shifted a little further right.
+    pub fn touch_qwerty() -> LayoutModel {
+        let rows = ["qwertyuiop", "asdfghjkl", "zxcvbnm"];
+        let mut positions = BTreeMap::new();
+        for (row, (keys, shift)) in rows.into_iter().zip([0.0, 0.25, 0.75]).enumerate() {
+            for (column, key) in keys.chars().enumerate() {
+                let position = KeyPosition {
+                    x: column as f64 + shift,
+                    y: row as f64,
+                    width: 1.0,
+                    height: 1.0,
+                };
+                positions.insert(key.to_string(), position);
+            }
+        }
+        LayoutModel {
+            positions: Some(positions),
+            ..layout("touch", LayoutKind::Keyboard, &rows)
+        }
+    }
 }
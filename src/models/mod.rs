@@ -1,3 +1,4 @@
 pub mod bigrams;
+pub mod jobs;
 pub mod layouts;
 pub mod pagination;
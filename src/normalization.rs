@@ -0,0 +1,246 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+use unidecode::unidecode;
+
+/// Unicode normalization form applied before anything else.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NormalizationForm {
+    None,
+    Nfc,
+    Nfkc,
+}
+
+/// What happens to Latin letters outside the protected ones. Letters of other scripts are always
+/// kept as written, since their diacritics often make distinct letters, like the Cyrillic "й".
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccentFolding {
+    /// Leaves every letter as typed.
+    Keep,
+    /// Drops combining diacritics, so "é" becomes "e" but "ø" stays.
+    Strip,
+    /// Transliterates to ASCII, so "é" becomes "e" and "ø" becomes "o".
+    Transliterate,
+}
+
+/// Turns words into the form they are counted and looked up in. The same pipeline must run at
+/// ingestion and at query time, or typed words will not match stored ones.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Normalizer {
+    pub form: NormalizationForm,
+    pub lowercase: bool,
+    pub accents: AccentFolding,
+    /// Letters that accent folding leaves alone, such as the "ñ" of Spanish, in either case.
+    pub protected: String,
+}
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Self {
+            form: NormalizationForm::Nfc,
+            lowercase: true,
+            accents: AccentFolding::Transliterate,
+            protected: "ñ".to_string(),
+        }
+    }
+}
+
+/// Reads `name` suffixed with the language code, such as `NORMALIZE_ACCENTS_ES` for "es", and
+/// falls back to `name` itself.
+fn var(name: &str, language: &str) -> Option<String> {
+    let suffix = language.to_uppercase().replace('-', "_");
+    std::env::var(format!("{name}_{suffix}"))
+        .or_else(|_| std::env::var(name))
+        .ok()
+}
+
+fn variant<T: DeserializeOwned>(name: &str, language: &str) -> Option<T> {
+    var(name, language).map(|value| {
+        serde_json::from_value(serde_json::Value::String(value))
+            .unwrap_or_else(|_| panic!("{name} has an unknown value"))
+    })
+}
+
+/// Whether `c` is a letter of a script other than Latin, such as Cyrillic, Greek or Han.
+fn is_other_script(c: char) -> bool {
+    let latin = matches!(
+        c,
+        '\u{0}'..='\u{24F}'
+            | '\u{1D00}'..='\u{1DBF}'
+            | '\u{1E00}'..='\u{1EFF}'
+            | '\u{2C60}'..='\u{2C7F}'
+            | '\u{A720}'..='\u{A7FF}'
+            | '\u{AB30}'..='\u{AB6F}'
+            | '\u{FB00}'..='\u{FB06}'
+            | '\u{FF21}'..='\u{FF3A}'
+            | '\u{FF41}'..='\u{FF5A}'
+    );
+    c.is_alphabetic() && !latin
+}
+
+impl Normalizer {
+    /// Reads `NORMALIZE_FORM` (`none`, `nfc` or `nfkc`), `NORMALIZE_LOWERCASE`,
+    /// `NORMALIZE_ACCENTS` (`keep`, `strip` or `transliterate`) and `NORMALIZE_PROTECTED`, each
+    /// of which can be overridden for `language` with its code as a suffix.
+    pub fn from_env(language: &str) -> Self {
+        let default = Self::default();
+
+        Self {
+            form: variant("NORMALIZE_FORM", language).unwrap_or(default.form),
+            lowercase: match var("NORMALIZE_LOWERCASE", language).as_deref() {
+                Some("true") => true,
+                Some("false") => false,
+                Some(_) => panic!("NORMALIZE_LOWERCASE must be true or false"),
+                None => default.lowercase,
+            },
+            accents: variant("NORMALIZE_ACCENTS", language).unwrap_or(default.accents),
+            protected: var("NORMALIZE_PROTECTED", language).unwrap_or(default.protected),
+        }
+    }
+
+    pub fn normalize(&self, word: &str) -> String {
+        self.apply(word, self.lowercase, self.accents)
+    }
+
+    /// The word as it should be displayed: in the same Unicode form as `normalize` puts it, but
+    /// with its case and accents intact.
+    pub fn surface(&self, word: &str) -> String {
+        self.apply(word, false, AccentFolding::Keep)
+    }
+
+    fn apply(&self, word: &str, lowercase: bool, accents: AccentFolding) -> String {
+        let word = match self.form {
+            NormalizationForm::None => word.to_string(),
+            NormalizationForm::Nfc => word.nfc().collect(),
+            NormalizationForm::Nfkc => word.nfkc().collect(),
+        };
+        let word = if lowercase { word.to_lowercase() } else { word };
+
+        let mut normalized = String::new();
+        let mut run = String::new();
+        let mut kept = false;
+        for c in word.chars() {
+            // Combining marks go with the letter before them.
+            kept = self.is_protected(c) || is_other_script(c) || (kept && is_combining_mark(c));
+            if kept {
+                normalized.push_str(&self.fold(&run, accents));
+                normalized.push(c);
+                run.clear();
+            } else {
+                run.push(c);
+            }
+        }
+        normalized.push_str(&self.fold(&run, accents));
+
+        // Transliterations may come out as several words, but a word never contains a space.
+        normalized.split_whitespace().collect()
+    }
+
+    fn is_protected(&self, c: char) -> bool {
+        self.protected
+            .chars()
+            .any(|protected| protected.to_lowercase().eq(c.to_lowercase()))
+    }
+
+    fn fold(&self, text: &str, accents: AccentFolding) -> String {
+        match accents {
+            AccentFolding::Keep => text.to_string(),
+            AccentFolding::Strip => {
+                let stripped = text.nfd().filter(|c| !is_combining_mark(*c));
+                match self.form {
+                    NormalizationForm::None => stripped.collect(),
+                    _ => stripped.nfc().collect(),
+                }
+            }
+            AccentFolding::Transliterate => unidecode(text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_matches_legacy_folding() {
+        let normalizer = Normalizer::default();
+
+        assert_eq!(normalizer.normalize("Niño"), "niño");
+        assert_eq!(normalizer.normalize("Canción"), "cancion");
+        assert_eq!(normalizer.normalize("Ñandú"), "ñandu");
+    }
+
+    #[test]
+    fn test_accent_folding() {
+        let keep = Normalizer {
+            accents: AccentFolding::Keep,
+            ..Normalizer::default()
+        };
+        assert_eq!(keep.normalize("Über"), "über");
+        // "e" followed by a combining acute accent is composed by NFC.
+        assert_eq!(keep.normalize("cafe\u{301}"), "café");
+
+        let strip = Normalizer {
+            accents: AccentFolding::Strip,
+            protected: "çü".to_string(),
+            ..Normalizer::default()
+        };
+        assert_eq!(strip.normalize("Façade"), "façade");
+        assert_eq!(strip.normalize("Über"), "über");
+        assert_eq!(strip.normalize("élève"), "eleve");
+        assert_eq!(strip.normalize("Øre"), "øre");
+    }
+
+    #[test]
+    fn test_surface_keeps_case_and_accents() {
+        let normalizer = Normalizer::default();
+
+        assert_eq!(normalizer.normalize("Canción"), "cancion");
+        assert_eq!(normalizer.surface("Canción"), "Canción");
+    }
+
+    #[test]
+    fn test_other_scripts_are_kept() {
+        let normalizer = Normalizer::default();
+
+        assert_eq!(normalizer.normalize("Москва"), "москва");
+        assert_eq!(normalizer.normalize("Йогурт"), "йогурт");
+        assert_eq!(normalizer.normalize("Αθήνα"), "αθήνα");
+        assert_eq!(normalizer.normalize("ΟΔΟΣ"), "οδος");
+        assert_eq!(normalizer.normalize("東京"), "東京");
+        assert_eq!(normalizer.normalize("Straße"), "strasse");
+
+        let strip = Normalizer {
+            accents: AccentFolding::Strip,
+            ..Normalizer::default()
+        };
+        assert_eq!(strip.normalize("Café"), "cafe");
+        assert_eq!(strip.normalize("йогурт"), "йогурт");
+    }
+
+    #[test]
+    fn test_compatibility_form() {
+        let normalizer = Normalizer {
+            form: NormalizationForm::Nfkc,
+            lowercase: false,
+            accents: AccentFolding::Keep,
+            protected: String::new(),
+        };
+
+        assert_eq!(normalizer.normalize("ﬁne"), "fine");
+        assert_eq!(normalizer.normalize("Ｗide"), "Wide");
+    }
+
+    #[test]
+    fn test_protected_in_either_case() {
+        let normalizer = Normalizer {
+            lowercase: false,
+            ..Normalizer::default()
+        };
+
+        assert_eq!(normalizer.normalize("NIÑO"), "NIÑO");
+        assert_eq!(normalizer.normalize("niño"), "niño");
+    }
+}
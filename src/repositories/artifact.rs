@@ -0,0 +1,153 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use log::{info, warn};
+
+use super::{BigramStore, Context, StoreError};
+use crate::{
+    artifact::ModelArtifact,
+    correction::PrefixEdits,
+    models::{
+        bigrams::{BigramModel, ContextCounts, SurfaceForm, WordCount},
+        layouts::LayoutModel,
+        pagination::Pagination,
+    },
+};
+
+/// Words whose surface forms are looked up at once while building an artifact.
+const FORMS_BATCH: usize = 1000;
+
+/// Returned by `ArtifactBigramRepo::build` when the wrapped store has no n-grams, such as on a
+/// replica serving an artifact built elsewhere, which must not be replaced by an empty one.
+#[derive(Debug)]
+pub struct NothingToExport;
+
+impl std::fmt::Display for NothingToExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The store has no n-grams to build an artifact from")
+    }
+}
+
+impl std::error::Error for NothingToExport {}
+
+/// Answers `find_counts`, `find_similar` and `find_forms` from a compiled `ModelArtifact` once
+/// one is loaded, falling back to the wrapped store before that. Writes always go to the wrapped
+/// store and only reach predictions after the next build.
+pub struct ArtifactBigramRepo {
+    inner: Arc<dyn BigramStore>,
+    path: PathBuf,
+    artifact: RwLock<Option<Arc<ModelArtifact>>>,
+}
+
+impl ArtifactBigramRepo {
+    pub fn init(inner: Arc<dyn BigramStore>, path: PathBuf) -> Self {
+        let repo = Self {
+            inner,
+            path,
+            artifact: RwLock::new(None),
+        };
+
+        match repo.reload() {
+            Ok(keys) => info!("Loaded model artifact with {keys} keys"),
+            Err(err) => warn!("Serving without model artifact: {err}"),
+        }
+        repo
+    }
+
+    /// Number of keys of the artifact being served, if any.
+    pub fn loaded(&self) -> Option<usize> {
+        self.artifact.read().unwrap().as_ref().map(|a| a.len())
+    }
+
+    /// Compiles the wrapped store and `layouts` into a new artifact and starts serving it.
+    pub async fn build(&self, layouts: &[LayoutModel]) -> Result<usize, StoreError> {
+        let ngrams = self.inner.export().await?;
+        if ngrams.is_empty() {
+            return Err(Box::new(NothingToExport));
+        }
+        let words = ngrams
+            .iter()
+            .map(|ngram| ngram.second.as_str())
+            .collect::<HashSet<&str>>()
+            .into_iter()
+            .collect::<Vec<&str>>();
+        let mut forms = HashMap::new();
+        for batch in words.chunks(FORMS_BATCH) {
+            forms.extend(self.inner.find_forms(batch).await?);
+        }
+
+        ModelArtifact::build(ngrams, forms, layouts, &self.path)?;
+        self.reload()
+    }
+
+    /// Maps the artifact file again, e.g. after another instance rebuilt it. Requests already
+    /// running keep the previous mapping until they finish.
+    pub fn reload(&self) -> Result<usize, StoreError> {
+        let artifact = ModelArtifact::load(&self.path)?;
+        let keys = artifact.len();
+        *self.artifact.write().unwrap() = Some(Arc::new(artifact));
+        Ok(keys)
+    }
+
+    /// The layout named `name` as of the artifact being served, if it has one.
+    pub fn find_layout(&self, name: &str) -> Option<LayoutModel> {
+        self.current()?.find_layout(name)
+    }
+
+    fn current(&self) -> Option<Arc<ModelArtifact>> {
+        self.artifact.read().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl BigramStore for ArtifactBigramRepo {
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+        self.inner.upsert_many(ngrams).await
+    }
+
+    async fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError> {
+        match self.current() {
+            Some(artifact) => Ok(artifact.find_counts(context, second, groups)),
+            None => self.inner.find_counts(context, second, groups).await,
+        }
+    }
+
+    async fn find_similar(
+        &self,
+        context: Context<'_>,
+        edits: &PrefixEdits<'_>,
+    ) -> Result<Vec<(WordCount, f64)>, StoreError> {
+        match self.current() {
+            Some(artifact) => Ok(artifact.find_similar(context, edits)),
+            None => self.inner.find_similar(context, edits).await,
+        }
+    }
+
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError> {
+        self.inner.find_all(pagination).await
+    }
+
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+        self.inner.export().await
+    }
+
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError> {
+        self.inner.upsert_forms(forms).await
+    }
+
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+        match self.current() {
+            Some(artifact) => Ok(artifact.find_forms(words)),
+            None => self.inner.find_forms(words).await,
+        }
+    }
+}
@@ -1,106 +1,327 @@
-use bson::doc;
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use bson::{doc, Bson, Document};
 use futures::stream::TryStreamExt;
 use mongodb::{
-    error::Error,
-    options::{FindOptions, IndexOptions, UpdateOptions},
-    results, IndexModel,
+    options::{FindOptions, IndexOptions},
+    IndexModel,
 };
 
+use super::{most_frequent, BigramStore, Context, PartialWrite, StoreError};
 use crate::{
     models::{
-        bigrams::{BigramModel, Prediction},
+        bigrams::{BigramModel, ContextCounts, SurfaceForm, WordCount},
         pagination::Pagination,
     },
-    utils::get_regex,
+    utils::{escape_regex, get_regex},
 };
 
+/// Largest number of updates sent in one `update` command.
+const BATCH_SIZE: usize = 10_000;
+
 #[derive(Clone)]
 pub struct BigramRepo {
     pub collection: mongodb::Collection<BigramModel>,
+    pub forms: mongodb::Collection<SurfaceForm>,
+    db: mongodb::Database,
 }
 
 impl BigramRepo {
-    pub async fn init(db: &mongodb::Database) -> Self {
+    /// Opens the `bigrams` and `forms` collections, creating their indexes.
+    pub async fn init(db: mongodb::Database, bigrams: &str, forms: &str) -> Self {
         let options = IndexOptions::builder().unique(true).build();
         let model = IndexModel::builder()
             .keys(doc! { "first": 1, "second": 1 })
             .options(options)
             .build();
-        let collection = db.collection::<BigramModel>("bigrams");
+        let collection = db.collection::<BigramModel>(bigrams);
 
         collection
             .create_index(model, None)
             .await
             .expect("Failed to create index on bigrams collection.");
 
-        Self { collection }
+        let options = IndexOptions::builder().unique(true).build();
+        let model = IndexModel::builder()
+            .keys(doc! { "word": 1, "form": 1 })
+            .options(options)
+            .build();
+        let forms = db.collection::<SurfaceForm>(forms);
+
+        forms
+            .create_index(model, None)
+            .await
+            .expect("Failed to create index on forms collection.");
+
+        Self {
+            collection,
+            forms,
+            db,
+        }
     }
 
-    pub async fn upsert(&self, first: &str, second: &str) -> Result<results::UpdateResult, Error> {
-        let filter = doc! {"first": first, "second": second};
-        let update = doc! {"$inc": {"count": 1}};
-        let options = UpdateOptions::builder().upsert(true).build();
-        self.collection.update_one(filter, update, options).await
+    /// Applies `$inc` upserts in unordered batches, so the server applies every update it can
+    /// instead of stopping at the first error, which is then reported per update.
+    async fn increment_many(
+        &self,
+        collection: &str,
+        updates: Vec<(Document, i64)>,
+    ) -> Result<(), StoreError> {
+        let mut failed = vec![];
+        let mut message = String::new();
+
+        for (batch, chunk) in updates.chunks(BATCH_SIZE).enumerate() {
+            let offset = batch * BATCH_SIZE;
+            let updates = chunk
+                .iter()
+                .map(|(filter, count)| {
+                    doc! {
+                        "q": filter,
+                        "u": {"$inc": {"count": count}},
+                        "upsert": true,
+                    }
+                })
+                .collect::<Vec<Document>>();
+            let command = doc! {
+                "update": collection,
+                "updates": updates,
+                "ordered": false,
+            };
+
+            match self.db.run_command(command, None).await {
+                Ok(result) => {
+                    if let Ok(errors) = result.get_array("writeErrors") {
+                        for error in errors.iter().filter_map(Bson::as_document) {
+                            let index = error.get_i32("index").unwrap_or(0) as usize;
+                            failed.push(offset + index);
+                            message = error.get_str("errmsg").unwrap_or_default().to_string();
+                        }
+                    }
+                }
+                Err(err) => {
+                    failed.extend(offset..offset + chunk.len());
+                    message = err.to_string();
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(Box::new(PartialWrite {
+                failed,
+                total: updates.len(),
+                message,
+            }))
+        }
     }
+}
 
-    pub async fn find_predictions(
+#[async_trait]
+impl BigramStore for BigramRepo {
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+        let updates = ngrams
+            .iter()
+            .map(|ngram| {
+                let filter = doc! {"first": &ngram.first, "second": &ngram.second};
+                (filter, ngram.count as i64)
+            })
+            .collect();
+        self.increment_many(self.collection.name(), updates).await
+    }
+
+    async fn find_counts(
         &self,
-        first: Option<&str>,
+        context: Context<'_>,
         second: Option<&str>,
-        keys: Vec<String>,
-    ) -> Result<Vec<Prediction>, Error> {
-        let (first, second) = match (first, second) {
-            (Some(first), Some(second)) => (
-                format!("^{}$", first),
-                format!("^{}", get_regex(second, keys)),
-            ),
-            (Some(first), None) => (format!("^{}$", first), "^.*".to_string()),
-            (None, Some(second)) => ("^.*".to_string(), format!("^{}", get_regex(second, keys))),
-            (None, None) => ("^.*".to_string(), "^.*".to_string()),
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError> {
+        let first = match context {
+            Context::Exact(context) => Bson::String(context.to_string()),
+            Context::Extending("") => Bson::Document(doc! {"$regex": "^[^ ]+$"}),
+            Context::Extending(suffix) => {
+                Bson::Document(doc! {"$regex": format!("^[^ ]+ {}$", escape_regex(suffix))})
+            }
+        };
+        let second = match second {
+            Some(second) => format!("^{}", get_regex(second, groups.to_vec())),
+            None => "^.*".to_string(),
         };
 
         let pipeline = vec![
-            doc! {"$match": {"first": {"$regex": &first} , "second": {"$regex": &second}}},
-            doc! {"$group": {"_id": null, "total": {"$sum": "$count"}}},
+            doc! {"$match": {"first": &first}},
+            doc! {"$group": {"_id": "$second", "count": {"$sum": "$count"}, "contexts": {"$sum": 1}}},
+            doc! {"$group": {
+                "_id": null,
+                "total": {"$sum": "$count"},
+                "total_contexts": {"$sum": "$contexts"},
+                "types": {"$sum": 1},
+            }},
+            doc! {"$project": {"_id": 0}},
         ];
 
-        let total_count = self
+        let mut counts = match self
             .collection
             .aggregate(pipeline, None)
             .await?
             .try_next()
             .await?
-            .unwrap_or(doc! {"total": 0})
-            .get_i32("total")
-            .unwrap();
+        {
+            Some(doc) => bson::from_document::<ContextCounts>(doc)?,
+            None => ContextCounts::default(),
+        };
 
         let pipeline = vec![
-            doc! {"$match": {"first": {"$regex": first} , "second": {"$regex": second}}},
-            doc! {"$group": {"_id": "$second", "count": {"$sum": "$count"}}},
-            doc! {"$project": {"_id": 0, "word": "$_id", "probability": {"$divide": ["$count", total_count]}}},
-            doc! {"$sort": {"probability": -1}},
+            doc! {"$match": {"first": first, "second": {"$regex": second}}},
+            doc! {"$group": {"_id": "$second", "count": {"$sum": "$count"}, "contexts": {"$sum": 1}}},
+            doc! {"$project": {"_id": 0, "word": "$_id", "count": 1, "contexts": 1}},
         ];
 
         let mut result = self.collection.aggregate(pipeline, None).await?;
 
-        let mut predictions = vec![];
         while let Some(doc) = result.try_next().await? {
-            let doc: Prediction = bson::from_document(doc)?;
-            predictions.push(doc);
+            let doc: WordCount = bson::from_document(doc)?;
+            counts.words.push(doc);
         }
-        Ok(predictions)
+        Ok(counts)
     }
 
-    pub async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, Error> {
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError> {
         let options = FindOptions::builder()
             .sort(doc! {"count": -1})
             .limit(pagination.limit.unwrap_or(10))
             .skip(pagination.offset.unwrap_or(0))
             .build();
-        self.collection
+        let bigrams = self
+            .collection
             .find(None, options)
             .await?
             .try_collect()
+            .await?;
+        Ok(bigrams)
+    }
+
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+        let bigrams = self
+            .collection
+            .find(None, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(bigrams)
+    }
+
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError> {
+        let updates = forms
+            .iter()
+            .map(|form| {
+                let filter = doc! {"word": &form.word, "form": &form.form};
+                (filter, form.count as i64)
+            })
+            .collect();
+        self.increment_many(self.forms.name(), updates).await
+    }
+
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+        let forms = self
+            .forms
+            .find(doc! {"word": {"$in": words}}, None)
+            .await?
+            .try_collect::<Vec<SurfaceForm>>()
+            .await?;
+        Ok(most_frequent(
+            forms
+                .into_iter()
+                .map(|form| (form.word, form.form, form.count as u64)),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ngram(first: &str, second: &str) -> BigramModel {
+        BigramModel {
+            id: None,
+            first: first.to_string(),
+            second: second.to_string(),
+            count: 1,
+        }
+    }
+
+    /// Runs against the server at `MONGO_URI`, in a database of its own that is dropped
+    /// afterwards: `MONGO_URI=mongodb://localhost cargo test -- --ignored`.
+    #[actix_web::test]
+    #[ignore]
+    async fn test_mongo_store() {
+        let uri = std::env::var("MONGO_URI").expect("MONGO_URI must be set");
+        let client = mongodb::Client::with_uri_str(&uri).await.unwrap();
+        let db = client.database(&format!("text_prediction_test_{}", std::process::id()));
+        let repo = BigramRepo::init(db.clone(), "bigrams", "forms").await;
+
+        // More n-grams than one update command takes, two of them counted twice.
+        let ngrams = (0..BATCH_SIZE + 5)
+            .map(|i| ngram("the", &format!("w{i}")))
+            .collect::<Vec<BigramModel>>();
+        repo.upsert_many(&ngrams).await.unwrap();
+        repo.upsert_many(&ngrams[..2]).await.unwrap();
+        assert_eq!(repo.export().await.unwrap().len(), BATCH_SIZE + 5);
+
+        let counts = repo
+            .find_counts(Context::Exact("the"), Some("w1"), &[])
+            .await
+            .unwrap();
+        assert_eq!(counts.total, BATCH_SIZE as u64 + 7);
+        assert_eq!(counts.types, BATCH_SIZE as u64 + 5);
+        // "w1", "w10" to "w19", "w100" to "w199", "w1000" to "w1999" and "w10000" to "w10004".
+        assert_eq!(counts.words.len(), 1116);
+        let w1 = counts.words.iter().find(|word| word.word == "w1").unwrap();
+        assert_eq!(w1.count, 2);
+
+        // Regex characters in contexts are matched as written, and typed letters by group.
+        let ngrams = [
+            ngram("a c.t", "sat"),
+            ngram("a cat", "sat"),
+            ngram("the c.t", "mat"),
+            ngram("the c.t", "rat"),
+        ];
+        repo.upsert_many(&ngrams).await.unwrap();
+        let counts = repo
+            .find_counts(Context::Extending("c.t"), Some("s"), &["sm".to_string()])
             .await
+            .unwrap();
+        assert_eq!(
+            (counts.total, counts.total_contexts, counts.types),
+            (3, 3, 3)
+        );
+        let mut words = counts
+            .words
+            .into_iter()
+            .map(|word| (word.word, word.count, word.contexts))
+            .collect::<Vec<(String, u64, u64)>>();
+        words.sort();
+        assert_eq!(
+            words,
+            [("mat".to_string(), 1, 1), ("sat".to_string(), 1, 1)]
+        );
+
+        let form = |form: &str, count: u32| SurfaceForm {
+            word: "paris".to_string(),
+            form: form.to_string(),
+            count,
+        };
+        repo.upsert_forms(&[form("paris", 2), form("Paris", 1)])
+            .await
+            .unwrap();
+        repo.upsert_forms(&[form("Paris", 2)]).await.unwrap();
+        let found = repo.find_forms(&["paris", "rome"]).await.unwrap();
+        assert_eq!(
+            found,
+            HashMap::from([("paris".to_string(), "Paris".to_string())])
+        );
+
+        db.drop(None).await.unwrap();
     }
 }
@@ -0,0 +1,103 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use log::info;
+
+use super::{BigramStore, Context, PartialWrite, StoreError};
+use crate::{
+    correction::PrefixEdits,
+    index::PrefixIndex,
+    models::{
+        bigrams::{BigramModel, ContextCounts, SurfaceForm, WordCount},
+        pagination::Pagination,
+    },
+};
+
+/// Writes through to a persistent store while answering `find_counts` and `find_similar` from
+/// memory, so that prediction latency does not grow with the corpus. Writes made by other server
+/// instances are only picked up on the next start.
+pub struct IndexedBigramRepo {
+    inner: Arc<dyn BigramStore>,
+    index: RwLock<PrefixIndex>,
+}
+
+impl IndexedBigramRepo {
+    pub async fn init(inner: Arc<dyn BigramStore>) -> Result<Self, StoreError> {
+        let mut index = PrefixIndex::default();
+
+        let ngrams = inner.export().await?;
+        info!("Loading {} n-grams into the prefix index", ngrams.len());
+        for ngram in ngrams {
+            index.insert(&ngram.first, &ngram.second, ngram.count as u64);
+        }
+
+        Ok(Self {
+            inner,
+            index: RwLock::new(index),
+        })
+    }
+}
+
+#[async_trait]
+impl BigramStore for IndexedBigramRepo {
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+        let result = self.inner.upsert_many(ngrams).await;
+
+        // Only index what was persisted so both stay in agreement after partial writes.
+        let failed = match &result {
+            Ok(()) => HashSet::new(),
+            Err(err) => match err.downcast_ref::<PartialWrite>() {
+                Some(partial) => partial.failed.iter().copied().collect(),
+                None => return result,
+            },
+        };
+
+        let mut index = self.index.write().unwrap();
+        for (i, ngram) in ngrams.iter().enumerate() {
+            if !failed.contains(&i) {
+                index.insert(&ngram.first, &ngram.second, ngram.count as u64);
+            }
+        }
+        result
+    }
+
+    async fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .find_counts(context, second, groups))
+    }
+
+    async fn find_similar(
+        &self,
+        context: Context<'_>,
+        edits: &PrefixEdits<'_>,
+    ) -> Result<Vec<(WordCount, f64)>, StoreError> {
+        Ok(self.index.read().unwrap().find_similar(context, edits))
+    }
+
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError> {
+        self.inner.find_all(pagination).await
+    }
+
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+        self.inner.export().await
+    }
+
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError> {
+        self.inner.upsert_forms(forms).await
+    }
+
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+        self.inner.find_forms(words).await
+    }
+}
@@ -0,0 +1,77 @@
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId};
+use futures::stream::TryStreamExt;
+use mongodb::{
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+    IndexModel,
+};
+
+use super::{JobStore, StoreError};
+use crate::models::jobs::JobModel;
+
+#[derive(Clone)]
+pub struct JobRepo {
+    pub collection: mongodb::Collection<JobModel>,
+}
+
+impl JobRepo {
+    /// Opens the `jobs` collection, indexed for the unfinished jobs of an instance.
+    pub async fn init(db: &mongodb::Database) -> Self {
+        let model = IndexModel::builder()
+            .keys(doc! { "owner": 1, "status": 1 })
+            .build();
+        let collection = db.collection::<JobModel>("jobs");
+
+        collection
+            .create_index(model, None)
+            .await
+            .expect("Failed to create index on jobs collection.");
+
+        Self { collection }
+    }
+}
+
+#[async_trait]
+impl JobStore for JobRepo {
+    async fn create(&self, job: &JobModel) -> Result<ObjectId, StoreError> {
+        let result = self.collection.insert_one(job, None).await?;
+        result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| "Inserted job id is not an ObjectId".into())
+    }
+
+    async fn find(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        let job = self.collection.find_one(doc! {"_id": id}, None).await?;
+        Ok(job)
+    }
+
+    async fn update(&self, job: &JobModel) -> Result<bool, StoreError> {
+        let filter = doc! {"_id": job.id, "status": {"$in": ["queued", "running"]}};
+        let result = self.collection.replace_one(filter, job, None).await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn cancel(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        let filter = doc! {"_id": id, "status": {"$in": ["queued", "running"]}};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let job = self
+            .collection
+            .find_one_and_update(filter, doc! {"$set": {"status": "cancelled"}}, options)
+            .await?;
+        Ok(job)
+    }
+
+    async fn find_unfinished(&self, owner: &str) -> Result<Vec<JobModel>, StoreError> {
+        let filter = doc! {"owner": owner, "status": {"$in": ["queued", "running"]}};
+        let jobs = self
+            .collection
+            .find(filter, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(jobs)
+    }
+}
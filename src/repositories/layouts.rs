@@ -1,7 +1,9 @@
-use bson::doc;
+use async_trait::async_trait;
+use bson::{doc, oid::ObjectId};
 use futures::stream::TryStreamExt;
-use mongodb::{error::Error, options::IndexOptions, results, IndexModel};
+use mongodb::{options::IndexOptions, IndexModel};
 
+use super::{LayoutStore, StoreError};
 use crate::models::layouts::LayoutModel;
 
 #[derive(Clone)]
@@ -25,32 +27,50 @@ impl LayoutRepo {
 
         Self { collection }
     }
+}
 
-    pub async fn find_all(&self) -> Result<Vec<LayoutModel>, Error> {
-        self.collection.find(None, None).await?.try_collect().await
+#[async_trait]
+impl LayoutStore for LayoutRepo {
+    async fn find_all(&self) -> Result<Vec<LayoutModel>, StoreError> {
+        let layouts = self
+            .collection
+            .find(None, None)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(layouts)
     }
 
-    pub async fn create(&self, layout: &LayoutModel) -> Result<results::InsertOneResult, Error> {
-        self.collection.insert_one(layout, None).await
+    async fn create(&self, layout: &LayoutModel) -> Result<ObjectId, StoreError> {
+        let result = self.collection.insert_one(layout, None).await?;
+        result
+            .inserted_id
+            .as_object_id()
+            .ok_or_else(|| "Inserted layout id is not an ObjectId".into())
     }
 
-    pub async fn find(&self, name: &str) -> Result<Option<LayoutModel>, Error> {
-        self.collection.find_one(doc! {"name": name}, None).await
+    async fn find(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let layout = self.collection.find_one(doc! {"name": name}, None).await?;
+        Ok(layout)
     }
 
-    pub async fn update(
+    async fn update(
         &self,
         name: &str,
         layout: &LayoutModel,
-    ) -> Result<Option<LayoutModel>, Error> {
-        self.collection
+    ) -> Result<Option<LayoutModel>, StoreError> {
+        let layout = self
+            .collection
             .find_one_and_replace(doc! {"name": name}, layout, None)
-            .await
+            .await?;
+        Ok(layout)
     }
 
-    pub async fn delete(&self, name: &str) -> Result<Option<LayoutModel>, Error> {
-        self.collection
+    async fn delete(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let layout = self
+            .collection
             .find_one_and_delete(doc! {"name": name}, None)
-            .await
+            .await?;
+        Ok(layout)
     }
 }
@@ -0,0 +1,218 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+
+use super::{most_frequent, BigramStore, Context, JobStore, LayoutStore, StoreError};
+use crate::{
+    correction::PrefixEdits,
+    index::PrefixIndex,
+    models::{
+        bigrams::{BigramModel, ContextCounts, SurfaceForm, WordCount},
+        jobs::{JobModel, JobStatus},
+        layouts::LayoutModel,
+        pagination::Pagination,
+    },
+};
+
+/// Keeps bigram counts in process memory, mainly for tests and database-less deployments.
+#[derive(Default)]
+pub struct MemoryBigramRepo {
+    index: RwLock<PrefixIndex>,
+    forms: RwLock<HashMap<(String, String), u64>>,
+}
+
+#[async_trait]
+impl BigramStore for MemoryBigramRepo {
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+        let mut index = self.index.write().unwrap();
+        for ngram in ngrams {
+            index.insert(&ngram.first, &ngram.second, ngram.count as u64);
+        }
+        Ok(())
+    }
+
+    async fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError> {
+        Ok(self
+            .index
+            .read()
+            .unwrap()
+            .find_counts(context, second, groups))
+    }
+
+    async fn find_similar(
+        &self,
+        context: Context<'_>,
+        edits: &PrefixEdits<'_>,
+    ) -> Result<Vec<(WordCount, f64)>, StoreError> {
+        Ok(self.index.read().unwrap().find_similar(context, edits))
+    }
+
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError> {
+        let mut bigrams = self.index.read().unwrap().ngrams();
+        bigrams.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| (&a.first, &a.second).cmp(&(&b.first, &b.second)))
+        });
+
+        Ok(bigrams
+            .into_iter()
+            .skip(pagination.offset.unwrap_or(0) as usize)
+            .take(pagination.limit.unwrap_or(10).max(0) as usize)
+            .collect())
+    }
+
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+        Ok(self.index.read().unwrap().ngrams())
+    }
+
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError> {
+        let mut stored = self.forms.write().unwrap();
+        for form in forms {
+            *stored
+                .entry((form.word.clone(), form.form.clone()))
+                .or_default() += form.count as u64;
+        }
+        Ok(())
+    }
+
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+        let stored = self.forms.read().unwrap();
+        Ok(most_frequent(
+            stored
+                .iter()
+                .filter(|((word, _), _)| words.contains(&word.as_str()))
+                .map(|((word, form), count)| (word.clone(), form.clone(), *count)),
+        ))
+    }
+}
+
+/// Keeps layouts in process memory, enforcing unique names like the Mongo index does.
+#[derive(Default)]
+pub struct MemoryLayoutRepo {
+    layouts: RwLock<Vec<LayoutModel>>,
+}
+
+#[async_trait]
+impl LayoutStore for MemoryLayoutRepo {
+    async fn find_all(&self) -> Result<Vec<LayoutModel>, StoreError> {
+        Ok(self.layouts.read().unwrap().clone())
+    }
+
+    async fn create(&self, layout: &LayoutModel) -> Result<ObjectId, StoreError> {
+        let mut layouts = self.layouts.write().unwrap();
+        if layouts.iter().any(|l| l.name == layout.name) {
+            return Err("Layout name already exists".into());
+        }
+
+        let id = layout.id.unwrap_or_default();
+        layouts.push(LayoutModel {
+            id: Some(id),
+            ..layout.clone()
+        });
+        Ok(id)
+    }
+
+    async fn find(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let layouts = self.layouts.read().unwrap();
+        Ok(layouts
+            .iter()
+            .find(|l| l.name.as_deref() == Some(name))
+            .cloned())
+    }
+
+    async fn update(
+        &self,
+        name: &str,
+        layout: &LayoutModel,
+    ) -> Result<Option<LayoutModel>, StoreError> {
+        let mut layouts = self.layouts.write().unwrap();
+        if layouts
+            .iter()
+            .any(|l| l.name == layout.name && l.name.as_deref() != Some(name))
+        {
+            return Err("Layout name already exists".into());
+        }
+
+        match layouts.iter_mut().find(|l| l.name.as_deref() == Some(name)) {
+            Some(existing) => {
+                let replacement = LayoutModel {
+                    id: existing.id,
+                    ..layout.clone()
+                };
+                Ok(Some(std::mem::replace(existing, replacement)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let mut layouts = self.layouts.write().unwrap();
+        let position = layouts.iter().position(|l| l.name.as_deref() == Some(name));
+        Ok(position.map(|i| layouts.remove(i)))
+    }
+}
+
+/// Keeps ingestion jobs in process memory, so they do not survive a restart.
+#[derive(Default)]
+pub struct MemoryJobRepo {
+    jobs: RwLock<Vec<JobModel>>,
+}
+
+#[async_trait]
+impl JobStore for MemoryJobRepo {
+    async fn create(&self, job: &JobModel) -> Result<ObjectId, StoreError> {
+        let id = job.id.unwrap_or_default();
+        self.jobs.write().unwrap().push(JobModel {
+            id: Some(id),
+            ..job.clone()
+        });
+        Ok(id)
+    }
+
+    async fn find(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        let jobs = self.jobs.read().unwrap();
+        Ok(jobs.iter().find(|j| j.id == Some(id)).cloned())
+    }
+
+    async fn update(&self, job: &JobModel) -> Result<bool, StoreError> {
+        let mut jobs = self.jobs.write().unwrap();
+        match jobs
+            .iter_mut()
+            .find(|j| j.id == job.id && !j.status.is_finished())
+        {
+            Some(existing) => {
+                *existing = job.clone();
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn cancel(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        let mut jobs = self.jobs.write().unwrap();
+        let job = jobs
+            .iter_mut()
+            .find(|j| j.id == Some(id) && !j.status.is_finished())
+            .map(|job| {
+                job.status = JobStatus::Cancelled;
+                job.clone()
+            });
+        Ok(job)
+    }
+
+    async fn find_unfinished(&self, owner: &str) -> Result<Vec<JobModel>, StoreError> {
+        let jobs = self.jobs.read().unwrap();
+        Ok(jobs
+            .iter()
+            .filter(|j| j.owner.as_deref() == Some(owner) && !j.status.is_finished())
+            .cloned()
+            .collect())
+    }
+}
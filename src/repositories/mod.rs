@@ -1,16 +1,281 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
 use mongodb::Client;
 
+use crate::{
+    correction::PrefixEdits,
+    models::{
+        bigrams::{BigramModel, ContextCounts, Prediction, SurfaceForm, WordCount},
+        jobs::JobModel,
+        layouts::LayoutModel,
+        pagination::Pagination,
+    },
+    smoothing::{self, Smoothing},
+    utils::matches_prefix,
+};
+
+pub mod artifact;
 pub mod bigrams;
+pub mod indexed;
+pub mod jobs;
 pub mod layouts;
+pub mod memory;
+pub mod sqlite;
+
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Returned by `BigramStore::upsert_many` when only some of the n-grams were written.
+#[derive(Debug)]
+pub struct PartialWrite {
+    /// Positions of the n-grams that were not written.
+    pub failed: Vec<usize>,
+    pub total: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} of {} n-grams were not written: {}",
+            self.failed.len(),
+            self.total,
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for PartialWrite {}
+
+/// Selects the n-grams whose counts `BigramStore::find_counts` aggregates.
+#[derive(Debug, Clone, Copy)]
+pub enum Context<'a> {
+    /// N-grams whose context is exactly these words. `Exact("")` is the unigram table.
+    Exact(&'a str),
+    /// N-grams whose context is one more word followed by these words, so that `contexts`
+    /// counts the distinct words seen before them. `Extending("")` covers every bigram.
+    Extending(&'a str),
+}
+
+impl Context<'_> {
+    pub fn matches(&self, first: &str) -> bool {
+        match self {
+            Context::Exact(context) => first == *context,
+            Context::Extending("") => !first.is_empty() && !first.contains(' '),
+            Context::Extending(suffix) => first
+                .strip_suffix(suffix)
+                .and_then(|head| head.strip_suffix(' '))
+                .is_some_and(|head| !head.is_empty() && !head.contains(' ')),
+        }
+    }
+}
+
+#[async_trait]
+pub trait BigramStore: Send + Sync {
+    /// Adds each n-gram's `count` to the stored one, creating missing n-grams. Either every
+    /// n-gram is written or the error says which were not, as a `PartialWrite` when possible.
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError>;
+
+    /// Aggregates the n-grams selected by `context` per following word. Totals cover every
+    /// word, while `words` only keeps those that start like `second`, where each typed letter
+    /// stands for any key of its ambiguity group in `groups`.
+    async fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError>;
+
+    /// Ranks the words that may follow `context` and start like `second` given the layout's
+    /// ambiguity `groups`.
+    async fn find_predictions(
+        &self,
+        context: &[&str],
+        second: Option<&str>,
+        groups: &[String],
+        smoothing: &Smoothing,
+    ) -> Result<Vec<Prediction>, StoreError> {
+        smoothing::find_predictions(self, context, second, groups, smoothing).await
+    }
+
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError>;
+
+    /// Every stored n-gram, in no particular order.
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError>;
+
+    /// Adds to the number of times each normalized word was written in each surface form.
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError>;
+
+    /// The most frequent surface form of each of `words` that has been seen.
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError>;
+
+    /// Counts of the words following `context` that `edits` finds to be corrections, with the
+    /// edits each takes. Stores without a trie or FST to walk find none rather than going
+    /// through every word.
+    async fn find_similar(
+        &self,
+        _context: Context<'_>,
+        _edits: &PrefixEdits<'_>,
+    ) -> Result<Vec<(WordCount, f64)>, StoreError> {
+        Ok(vec![])
+    }
+}
+
+#[async_trait]
+pub trait LayoutStore: Send + Sync {
+    async fn find_all(&self) -> Result<Vec<LayoutModel>, StoreError>;
+
+    async fn create(&self, layout: &LayoutModel) -> Result<ObjectId, StoreError>;
+
+    async fn find(&self, name: &str) -> Result<Option<LayoutModel>, StoreError>;
+
+    async fn update(
+        &self,
+        name: &str,
+        layout: &LayoutModel,
+    ) -> Result<Option<LayoutModel>, StoreError>;
+
+    async fn delete(&self, name: &str) -> Result<Option<LayoutModel>, StoreError>;
+}
+
+#[async_trait]
+pub trait JobStore: Send + Sync {
+    async fn create(&self, job: &JobModel) -> Result<ObjectId, StoreError>;
+
+    async fn find(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError>;
+
+    /// Replaces the stored job unless it already finished, returning whether it was replaced.
+    /// Finished jobs are never overwritten, which is how a running job learns it was cancelled.
+    async fn update(&self, job: &JobModel) -> Result<bool, StoreError>;
+
+    /// Marks the job cancelled unless it already finished, leaving its progress as its worker
+    /// last saved it. Returns the cancelled job, or `None` when no unfinished job has that id.
+    async fn cancel(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError>;
+
+    /// Queued and running jobs of the instance named `owner`.
+    async fn find_unfinished(&self, owner: &str) -> Result<Vec<JobModel>, StoreError>;
+}
+
+/// Groups `(first, second, count)` rows the way `BigramStore::find_counts` reports them.
+fn aggregate_counts<'a>(
+    rows: impl IntoIterator<Item = (&'a str, &'a str, u32)>,
+    context: Context<'_>,
+    second: Option<&str>,
+    groups: &[String],
+) -> ContextCounts {
+    let mut words = HashMap::<&str, WordCount>::new();
+    for (first, word, count) in rows {
+        if context.matches(first) {
+            let entry = words.entry(word).or_insert_with(|| WordCount {
+                word: word.to_string(),
+                count: 0,
+                contexts: 0,
+            });
+            entry.count += count as u64;
+            entry.contexts += 1;
+        }
+    }
+
+    let mut counts = ContextCounts {
+        total: words.values().map(|word| word.count).sum(),
+        total_contexts: words.values().map(|word| word.contexts).sum(),
+        types: words.len() as u64,
+        words: vec![],
+    };
+    counts.words = words
+        .into_values()
+        .filter(|word| second.is_none_or(|second| matches_prefix(&word.word, second, groups)))
+        .collect();
+    counts
+}
+
+/// Picks the surface form with the highest count per word, the first in order on ties.
+fn most_frequent(
+    forms: impl IntoIterator<Item = (String, String, u64)>,
+) -> HashMap<String, String> {
+    let mut best = HashMap::<String, (String, u64)>::new();
+    for (word, form, count) in forms {
+        let better = best.get(&word).is_none_or(|(best_form, best_count)| {
+            count > *best_count || (count == *best_count && form < *best_form)
+        });
+        if better {
+            best.insert(word, (form, count));
+        }
+    }
+    best.into_iter()
+        .map(|(word, (form, _))| (word, form))
+        .collect()
+}
+
+/// Name of the `base` collection or table of the language at `position` in the configured list.
+/// The default language keeps the names used before there were several languages.
+fn scoped(base: &str, position: usize, language: &str) -> String {
+    if position == 0 {
+        base.to_string()
+    } else {
+        format!("{base}_{}", language.replace('-', "_"))
+    }
+}
+
+/// The n-gram counts of one language.
+#[derive(Clone)]
+pub struct LanguageModel {
+    pub language: String,
+    pub bigrams: Arc<dyn BigramStore>,
+    /// Set when predictions are served from a compiled model artifact.
+    pub artifact: Option<Arc<artifact::ArtifactBigramRepo>>,
+}
 
 #[derive(Clone)]
-pub struct MongoRepo {
-    pub layouts: layouts::LayoutRepo,
-    pub bigrams: bigrams::BigramRepo,
+pub struct Repo {
+    pub layouts: Arc<dyn LayoutStore>,
+    pub jobs: Arc<dyn JobStore>,
+    /// One model per configured language, the default one first.
+    pub models: Vec<LanguageModel>,
 }
 
-impl MongoRepo {
-    pub async fn init(db_name: &str) -> Self {
+impl Repo {
+    /// Picks the storage backend from `STORAGE_BACKEND` (`mongo`, `sqlite` or `memory`).
+    /// Predictions are served from the compiled artifact at `MODEL_ARTIFACT` when that is set,
+    /// and otherwise by persistent backends from a prefix index unless `PREFIX_INDEX=false`.
+    pub async fn init(languages: &[&str]) -> Self {
+        let backend = std::env::var("STORAGE_BACKEND").unwrap_or("mongo".to_string());
+        let prefix_index = std::env::var("PREFIX_INDEX").unwrap_or("true".to_string()) != "false";
+
+        let model_artifact = std::env::var("MODEL_ARTIFACT").ok();
+
+        let repo = match backend.as_str() {
+            "mongo" => {
+                let db_name = std::env::var("MONGO_DB").expect("MONGO_DB must be set");
+                Self::mongo(&db_name, languages).await
+            }
+            "sqlite" => {
+                let path = std::env::var("SQLITE_PATH").unwrap_or("text_prediction.db".to_string());
+                Self::sqlite(&path, languages)
+            }
+            "memory" => Self::memory(languages),
+            _ => panic!("STORAGE_BACKEND must be one of: mongo, sqlite, memory"),
+        };
+
+        let repo = if prefix_index && backend != "memory" && model_artifact.is_none() {
+            repo.with_prefix_index().await
+        } else {
+            repo
+        };
+
+        match model_artifact {
+            Some(path) => repo.with_artifact(PathBuf::from(path)),
+            None => repo,
+        }
+    }
+
+    pub async fn mongo(db_name: &str, languages: &[&str]) -> Self {
         let mongo_uri = std::env::var("MONGO_URI").expect("MONGO_URI must be set");
 
         let client = Client::with_uri_str(&mongo_uri)
@@ -19,23 +284,122 @@ impl MongoRepo {
         let db = client.database(db_name);
 
         let layouts = layouts::LayoutRepo::init(&db).await;
-        let bigrams = bigrams::BigramRepo::init(&db).await;
+        let jobs = jobs::JobRepo::init(&db).await;
+        let mut models = vec![];
+        for (position, language) in languages.iter().enumerate() {
+            let bigrams = bigrams::BigramRepo::init(
+                db.clone(),
+                &scoped("bigrams", position, language),
+                &scoped("forms", position, language),
+            )
+            .await;
+            models.push(LanguageModel {
+                language: language.to_string(),
+                bigrams: Arc::new(bigrams),
+                artifact: None,
+            });
+        }
 
         Self {
-            layouts,
-            bigrams,
+            layouts: Arc::new(layouts),
+            jobs: Arc::new(jobs),
+            models,
         }
     }
 
-    #[allow(dead_code)]
-    pub async fn drop(db_name: &str) {
-        let mongo_uri = std::env::var("MONGO_URI").expect("MONGO_URI must be set");
+    pub fn sqlite(path: &str, languages: &[&str]) -> Self {
+        let connection = rusqlite::Connection::open(path).expect("Failed to open SQLite database.");
+        let connection = Arc::new(Mutex::new(connection));
 
-        let client = Client::with_uri_str(&mongo_uri)
-            .await
-            .expect("Failed to initialize client.");
-        let db = client.database(db_name);
+        let layouts = sqlite::SqliteLayoutRepo::init(connection.clone());
+        let jobs = sqlite::SqliteJobRepo::init(connection.clone());
+        let models = languages
+            .iter()
+            .enumerate()
+            .map(|(position, language)| LanguageModel {
+                language: language.to_string(),
+                bigrams: Arc::new(sqlite::SqliteBigramRepo::init(
+                    connection.clone(),
+                    &scoped("bigrams", position, language),
+                    &scoped("forms", position, language),
+                )),
+                artifact: None,
+            })
+            .collect();
+
+        Self {
+            layouts: Arc::new(layouts),
+            jobs: Arc::new(jobs),
+            models,
+        }
+    }
+
+    /// Loads every n-gram into a `PrefixIndex` that serves `find_counts` from then on.
+    pub async fn with_prefix_index(self) -> Self {
+        let mut models = vec![];
+        for model in self.models {
+            let bigrams = indexed::IndexedBigramRepo::init(model.bigrams)
+                .await
+                .expect("Failed to load the prefix index.");
+            models.push(LanguageModel {
+                bigrams: Arc::new(bigrams),
+                ..model
+            });
+        }
+
+        Self { models, ..self }
+    }
+
+    /// Serves predictions from the artifact at `path`, which can be built and reloaded later.
+    /// Languages other than the default one get their code inserted before the extension.
+    pub fn with_artifact(self, path: PathBuf) -> Self {
+        let models = self
+            .models
+            .into_iter()
+            .enumerate()
+            .map(|(position, model)| {
+                let path = match path.extension() {
+                    _ if position == 0 => path.clone(),
+                    Some(extension) => path.with_extension(format!(
+                        "{}.{}",
+                        model.language,
+                        extension.to_string_lossy()
+                    )),
+                    None => path.with_extension(&model.language),
+                };
+                let artifact = Arc::new(artifact::ArtifactBigramRepo::init(model.bigrams, path));
+                LanguageModel {
+                    language: model.language,
+                    bigrams: artifact.clone(),
+                    artifact: Some(artifact),
+                }
+            })
+            .collect();
+
+        Self {
+            layouts: self.layouts,
+            jobs: self.jobs,
+            models,
+        }
+    }
+
+    pub fn memory(languages: &[&str]) -> Self {
+        Self {
+            layouts: Arc::new(memory::MemoryLayoutRepo::default()),
+            jobs: Arc::new(memory::MemoryJobRepo::default()),
+            models: languages
+                .iter()
+                .map(|language| LanguageModel {
+                    language: language.to_string(),
+                    bigrams: Arc::new(memory::MemoryBigramRepo::default()),
+                    artifact: None,
+                })
+                .collect(),
+        }
+    }
 
-        db.drop(None).await.expect("Failed to drop database.");
+    /// The model of `language`, if it is one of the configured languages.
+    pub fn model(&self, language: &str) -> Option<&LanguageModel> {
+        self.models.iter().find(|model| model.language == language)
     }
 }
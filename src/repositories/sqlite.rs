@@ -0,0 +1,479 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use bson::oid::ObjectId;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{
+    aggregate_counts, most_frequent, BigramStore, Context, JobStore, LayoutStore, StoreError,
+};
+use crate::{
+    models::{
+        bigrams::{BigramModel, ContextCounts, SurfaceForm},
+        jobs::JobModel,
+        layouts::LayoutModel,
+        pagination::Pagination,
+    },
+    utils::saturating_u32,
+};
+
+/// Embedded bigram storage for deployments that cannot run MongoDB.
+pub struct SqliteBigramRepo {
+    connection: Arc<Mutex<Connection>>,
+    /// Table of the n-gram counts.
+    bigrams: String,
+    /// Table of the surface forms.
+    forms: String,
+}
+
+impl SqliteBigramRepo {
+    /// Opens the `bigrams` and `forms` tables, creating them if needed. Table names are trusted.
+    pub fn init(connection: Arc<Mutex<Connection>>, bigrams: &str, forms: &str) -> Self {
+        connection
+            .lock()
+            .unwrap()
+            .execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {bigrams} (
+                    first TEXT NOT NULL,
+                    second TEXT NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (first, second)
+                );
+                CREATE TABLE IF NOT EXISTS {forms} (
+                    word TEXT NOT NULL,
+                    form TEXT NOT NULL,
+                    count INTEGER NOT NULL,
+                    PRIMARY KEY (word, form)
+                )"
+            ))
+            .expect("Failed to create bigrams tables.");
+
+        Self {
+            connection,
+            bigrams: bigrams.to_string(),
+            forms: forms.to_string(),
+        }
+    }
+}
+
+/// Runs `work` on the connection in the blocking thread pool, so that long writes and scans
+/// hold up neither the server's workers nor the other requests they serve meanwhile.
+async fn blocking<T: Send + 'static>(
+    connection: &Arc<Mutex<Connection>>,
+    work: impl FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+) -> Result<T, StoreError> {
+    let connection = connection.clone();
+    actix_web::rt::task::spawn_blocking(move || work(&mut connection.lock().unwrap())).await?
+}
+
+/// Reads a count column. Counts are summed without bound when upserted, so they are clamped
+/// to the range of `BigramModel::count` here rather than failing every later read.
+fn read_count(row: &rusqlite::Row, index: usize) -> rusqlite::Result<u32> {
+    Ok(saturating_u32(row.get::<_, i64>(index)?.max(0) as u64))
+}
+
+#[async_trait]
+impl BigramStore for SqliteBigramRepo {
+    async fn upsert_many(&self, ngrams: &[BigramModel]) -> Result<(), StoreError> {
+        let (table, ngrams) = (self.bigrams.clone(), ngrams.to_vec());
+        blocking(&self.connection, move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare(&format!(
+                    "INSERT INTO {table} (first, second, count) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (first, second) DO UPDATE SET count = count + excluded.count"
+                ))?;
+                for ngram in ngrams {
+                    statement.execute(params![ngram.first, ngram.second, ngram.count])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_counts(
+        &self,
+        context: Context<'_>,
+        second: Option<&str>,
+        groups: &[String],
+    ) -> Result<ContextCounts, StoreError> {
+        let (filter, param) = match context {
+            Context::Exact(context) => ("first = ?1", context),
+            Context::Extending("") => ("instr(first, ' ') = 0 AND ?1 = ''", ""),
+            Context::Extending(suffix) => ("substr(first, -length(?1) - 1) = ' ' || ?1", suffix),
+        };
+        let (table, param) = (self.bigrams.clone(), param.to_string());
+        let rows = blocking(&self.connection, move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT first, second, count FROM {table} WHERE {filter}"
+            ))?;
+            let rows = statement
+                .query_map(params![param], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        read_count(row, 2)?,
+                    ))
+                })?
+                .collect::<Result<Vec<(String, String, u32)>, _>>()?;
+            Ok(rows)
+        })
+        .await?;
+
+        let rows = rows
+            .iter()
+            .map(|(first, second, count)| (first.as_str(), second.as_str(), *count));
+        Ok(aggregate_counts(rows, context, second, groups))
+    }
+
+    async fn find_all(&self, pagination: Pagination) -> Result<Vec<BigramModel>, StoreError> {
+        let table = self.bigrams.clone();
+        blocking(&self.connection, move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT first, second, count FROM {table}
+                ORDER BY count DESC, first, second
+                LIMIT ?1 OFFSET ?2"
+            ))?;
+            let bigrams = statement
+                .query_map(
+                    params![
+                        pagination.limit.unwrap_or(10),
+                        pagination.offset.unwrap_or(0)
+                    ],
+                    parse_bigram,
+                )?
+                .collect::<Result<Vec<BigramModel>, _>>()?;
+            Ok(bigrams)
+        })
+        .await
+    }
+
+    async fn export(&self) -> Result<Vec<BigramModel>, StoreError> {
+        let table = self.bigrams.clone();
+        blocking(&self.connection, move |connection| {
+            let mut statement =
+                connection.prepare(&format!("SELECT first, second, count FROM {table}"))?;
+            let bigrams = statement
+                .query_map([], parse_bigram)?
+                .collect::<Result<Vec<BigramModel>, _>>()?;
+            Ok(bigrams)
+        })
+        .await
+    }
+
+    async fn upsert_forms(&self, forms: &[SurfaceForm]) -> Result<(), StoreError> {
+        let (table, forms) = (self.forms.clone(), forms.to_vec());
+        blocking(&self.connection, move |connection| {
+            let transaction = connection.transaction()?;
+            {
+                let mut statement = transaction.prepare(&format!(
+                    "INSERT INTO {table} (word, form, count) VALUES (?1, ?2, ?3)
+                    ON CONFLICT (word, form) DO UPDATE SET count = count + excluded.count"
+                ))?;
+                for form in forms {
+                    statement.execute(params![form.word, form.form, form.count])?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_forms(&self, words: &[&str]) -> Result<HashMap<String, String>, StoreError> {
+        let table = self.forms.clone();
+        let words = words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<String>>();
+        let forms = blocking(&self.connection, move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT word, form, count FROM {table} WHERE word = ?1"
+            ))?;
+            let mut forms = vec![];
+            for word in words {
+                let rows = statement.query_map(params![word], |row| {
+                    Ok((row.get(0)?, row.get(1)?, read_count(row, 2)? as u64))
+                })?;
+                for row in rows {
+                    forms.push(row?);
+                }
+            }
+            Ok(forms)
+        })
+        .await?;
+        Ok(most_frequent(forms))
+    }
+}
+
+fn parse_bigram(row: &rusqlite::Row) -> rusqlite::Result<BigramModel> {
+    Ok(BigramModel {
+        id: None,
+        first: row.get(0)?,
+        second: row.get(1)?,
+        count: read_count(row, 2)?,
+    })
+}
+
+/// Embedded layout storage. Layouts are kept as JSON documents keyed by their unique name.
+pub struct SqliteLayoutRepo {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteLayoutRepo {
+    pub fn init(connection: Arc<Mutex<Connection>>) -> Self {
+        connection
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS layouts (
+                    id TEXT PRIMARY KEY,
+                    name TEXT UNIQUE,
+                    layout TEXT NOT NULL
+                )",
+            )
+            .expect("Failed to create layouts table.");
+
+        Self { connection }
+    }
+}
+
+fn parse_layout(layout: String) -> Result<LayoutModel, StoreError> {
+    Ok(serde_json::from_str(&layout)?)
+}
+
+#[async_trait]
+impl LayoutStore for SqliteLayoutRepo {
+    async fn find_all(&self) -> Result<Vec<LayoutModel>, StoreError> {
+        blocking(&self.connection, |connection| {
+            let mut statement = connection.prepare("SELECT layout FROM layouts ORDER BY rowid")?;
+            let layouts = statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            layouts.into_iter().map(parse_layout).collect()
+        })
+        .await
+    }
+
+    async fn create(&self, layout: &LayoutModel) -> Result<ObjectId, StoreError> {
+        let id = layout.id.unwrap_or_default();
+        let layout = LayoutModel {
+            id: Some(id),
+            ..layout.clone()
+        };
+
+        blocking(&self.connection, move |connection| {
+            connection.execute(
+                "INSERT INTO layouts (id, name, layout) VALUES (?1, ?2, ?3)",
+                params![id.to_hex(), layout.name, serde_json::to_string(&layout)?],
+            )?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn find(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let name = name.to_string();
+        blocking(&self.connection, move |connection| {
+            let layout = connection
+                .query_row(
+                    "SELECT layout FROM layouts WHERE name = ?1",
+                    params![name],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            layout.map(parse_layout).transpose()
+        })
+        .await
+    }
+
+    async fn update(
+        &self,
+        name: &str,
+        layout: &LayoutModel,
+    ) -> Result<Option<LayoutModel>, StoreError> {
+        let (name, layout) = (name.to_string(), layout.clone());
+        blocking(&self.connection, move |connection| {
+            let transaction = connection.transaction()?;
+
+            let existing = transaction
+                .query_row(
+                    "SELECT layout FROM layouts WHERE name = ?1",
+                    params![name],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?
+                .map(parse_layout)
+                .transpose()?;
+
+            if let Some(existing) = &existing {
+                let layout = LayoutModel {
+                    id: existing.id,
+                    ..layout
+                };
+                transaction.execute(
+                    "UPDATE layouts SET name = ?1, layout = ?2 WHERE name = ?3",
+                    params![layout.name, serde_json::to_string(&layout)?, name],
+                )?;
+            }
+
+            transaction.commit()?;
+            Ok(existing)
+        })
+        .await
+    }
+
+    async fn delete(&self, name: &str) -> Result<Option<LayoutModel>, StoreError> {
+        let name = name.to_string();
+        blocking(&self.connection, move |connection| {
+            let layout = connection
+                .query_row(
+                    "DELETE FROM layouts WHERE name = ?1 RETURNING layout",
+                    params![name],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            layout.map(parse_layout).transpose()
+        })
+        .await
+    }
+}
+
+/// Embedded job storage. Jobs are kept as JSON documents so their status can be filtered on.
+pub struct SqliteJobRepo {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteJobRepo {
+    pub fn init(connection: Arc<Mutex<Connection>>) -> Self {
+        connection
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS jobs (
+                    id TEXT PRIMARY KEY,
+                    job TEXT NOT NULL
+                )",
+            )
+            .expect("Failed to create jobs table.");
+
+        Self { connection }
+    }
+}
+
+fn parse_job(job: String) -> Result<JobModel, StoreError> {
+    Ok(serde_json::from_str(&job)?)
+}
+
+#[async_trait]
+impl JobStore for SqliteJobRepo {
+    async fn create(&self, job: &JobModel) -> Result<ObjectId, StoreError> {
+        let id = job.id.unwrap_or_default();
+        let job = JobModel {
+            id: Some(id),
+            ..job.clone()
+        };
+
+        blocking(&self.connection, move |connection| {
+            connection.execute(
+                "INSERT INTO jobs (id, job) VALUES (?1, ?2)",
+                params![id.to_hex(), serde_json::to_string(&job)?],
+            )?;
+            Ok(id)
+        })
+        .await
+    }
+
+    async fn find(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        blocking(&self.connection, move |connection| {
+            let job = connection
+                .query_row(
+                    "SELECT job FROM jobs WHERE id = ?1",
+                    params![id.to_hex()],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            job.map(parse_job).transpose()
+        })
+        .await
+    }
+
+    async fn update(&self, job: &JobModel) -> Result<bool, StoreError> {
+        let id = job.id.ok_or("Job has no id")?;
+        let job = serde_json::to_string(job)?;
+        blocking(&self.connection, move |connection| {
+            let changed = connection.execute(
+                "UPDATE jobs SET job = ?1
+                WHERE id = ?2 AND json_extract(job, '$.status') IN ('queued', 'running')",
+                params![job, id.to_hex()],
+            )?;
+            Ok(changed > 0)
+        })
+        .await
+    }
+
+    async fn cancel(&self, id: ObjectId) -> Result<Option<JobModel>, StoreError> {
+        blocking(&self.connection, move |connection| {
+            let job = connection
+                .query_row(
+                    "UPDATE jobs SET job = json_set(job, '$.status', 'cancelled')
+                    WHERE id = ?1 AND json_extract(job, '$.status') IN ('queued', 'running')
+                    RETURNING job",
+                    params![id.to_hex()],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()?;
+            job.map(parse_job).transpose()
+        })
+        .await
+    }
+
+    async fn find_unfinished(&self, owner: &str) -> Result<Vec<JobModel>, StoreError> {
+        let owner = owner.to_string();
+        blocking(&self.connection, move |connection| {
+            let mut statement = connection.prepare(
+                "SELECT job FROM jobs WHERE json_extract(job, '$.owner') = ?1
+                AND json_extract(job, '$.status') IN ('queued', 'running')",
+            )?;
+            let jobs = statement
+                .query_map(params![owner], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            jobs.into_iter().map(parse_job).collect()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_counts_past_u32() {
+        let connection = Arc::new(Mutex::new(Connection::open_in_memory().unwrap()));
+        let repo = SqliteBigramRepo::init(connection, "bigrams", "forms");
+        let ngram = BigramModel {
+            id: None,
+            first: "the".to_string(),
+            second: "cat".to_string(),
+            count: u32::MAX,
+        };
+        for _ in 0..2 {
+            repo.upsert_many(std::slice::from_ref(&ngram))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(repo.export().await.unwrap()[0].count, u32::MAX);
+        let counts = repo
+            .find_counts(Context::Exact("the"), None, &[])
+            .await
+            .unwrap();
+        assert_eq!(counts.words[0].count, u32::MAX as u64);
+    }
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::bigrams::{ContextCounts, Prediction, PredictionKind},
+    repositories::{BigramStore, Context, StoreError},
+};
+
+/// How n-gram counts are turned into prediction probabilities.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Smoothing {
+    /// Relative frequency among the matching words of the longest context that has any.
+    Mle,
+    /// Adds `k` to the count of every vocabulary word after the longest seen context.
+    AddK {
+        #[serde(default = "default_k")]
+        k: f64,
+    },
+    /// Interpolated Kneser-Ney with an absolute `discount` between 0 and 1.
+    KneserNey {
+        #[serde(default = "default_discount")]
+        discount: f64,
+    },
+}
+
+fn default_k() -> f64 {
+    1.0
+}
+
+fn default_discount() -> f64 {
+    0.75
+}
+
+impl Smoothing {
+    /// Reads `SMOOTHING` (`mle`, `add_k` or `kneser_ney`) with its optional `SMOOTHING_K` and
+    /// `SMOOTHING_DISCOUNT` parameters.
+    pub fn from_env() -> Self {
+        let method = std::env::var("SMOOTHING").unwrap_or("mle".to_string());
+        let parameter = |name: &str, default: f64| {
+            std::env::var(name)
+                .map(|value| {
+                    value
+                        .parse::<f64>()
+                        .expect("Smoothing parameters must be numbers")
+                })
+                .unwrap_or(default)
+        };
+
+        match method.as_str() {
+            "mle" => Smoothing::Mle,
+            "add_k" => Smoothing::AddK {
+                k: parameter("SMOOTHING_K", default_k()),
+            },
+            "kneser_ney" => Smoothing::KneserNey {
+                discount: parameter("SMOOTHING_DISCOUNT", default_discount()),
+            },
+            _ => panic!("SMOOTHING must be one of: mle, add_k, kneser_ney"),
+        }
+    }
+}
+
+pub async fn find_predictions<S: BigramStore + ?Sized>(
+    store: &S,
+    context: &[&str],
+    second: Option<&str>,
+    groups: &[String],
+    smoothing: &Smoothing,
+) -> Result<Vec<Prediction>, StoreError> {
+    let probabilities = match smoothing {
+        Smoothing::Mle => maximum_likelihood(store, context, second, groups).await?,
+        Smoothing::AddK { k } => add_k(store, context, second, groups, *k).await?,
+        Smoothing::KneserNey { discount } => {
+            kneser_ney(store, context, second, groups, discount.clamp(0.0, 1.0)).await?
+        }
+    };
+
+    let mut predictions = probabilities
+        .into_iter()
+        .map(|(word, probability)| Prediction {
+            word,
+            probability,
+            kind: PredictionKind::Completion,
+        })
+        .collect::<Vec<Prediction>>();
+    predictions.sort_by(|a, b| {
+        b.probability
+            .total_cmp(&a.probability)
+            .then_with(|| a.word.cmp(&b.word))
+    });
+    Ok(predictions)
+}
+
+/// Suffixes of `context` from the longest to the shortest non-empty one.
+pub fn backoff<'a>(context: &'a [&str]) -> impl Iterator<Item = String> + 'a {
+    (1..=context.len())
+        .rev()
+        .map(move |n| context[context.len() - n..].join(" "))
+}
+
+/// Unigram counts, falling back to bigram counts for corpora ingested before unigrams were kept.
+pub async fn vocabulary<S: BigramStore + ?Sized>(
+    store: &S,
+    second: Option<&str>,
+    groups: &[String],
+) -> Result<ContextCounts, StoreError> {
+    let unigrams = store
+        .find_counts(Context::Exact(""), second, groups)
+        .await?;
+    if unigrams.types > 0 {
+        return Ok(unigrams);
+    }
+    store
+        .find_counts(Context::Extending(""), second, groups)
+        .await
+}
+
+async fn maximum_likelihood<S: BigramStore + ?Sized>(
+    store: &S,
+    context: &[&str],
+    second: Option<&str>,
+    groups: &[String],
+) -> Result<HashMap<String, f64>, StoreError> {
+    let mut counts = ContextCounts::default();
+    for first in backoff(context) {
+        counts = store
+            .find_counts(Context::Exact(&first), second, groups)
+            .await?;
+        if !counts.words.is_empty() {
+            break;
+        }
+    }
+    if counts.words.is_empty() {
+        counts = vocabulary(store, second, groups).await?;
+    }
+
+    let total = counts.words.iter().map(|word| word.count).sum::<u64>() as f64;
+    Ok(counts
+        .words
+        .into_iter()
+        .map(|word| (word.word, word.count as f64 / total))
+        .collect())
+}
+
+async fn add_k<S: BigramStore + ?Sized>(
+    store: &S,
+    context: &[&str],
+    second: Option<&str>,
+    groups: &[String],
+    k: f64,
+) -> Result<HashMap<String, f64>, StoreError> {
+    let vocabulary = vocabulary(store, second, groups).await?;
+
+    let mut counts = None;
+    for first in backoff(context) {
+        let found = store
+            .find_counts(Context::Exact(&first), second, groups)
+            .await?;
+        if found.total > 0 {
+            counts = Some(found);
+            break;
+        }
+    }
+    let counts = counts.unwrap_or(vocabulary.clone());
+
+    let seen = counts
+        .words
+        .iter()
+        .map(|word| (word.word.as_str(), word.count))
+        .collect::<HashMap<&str, u64>>();
+    let denominator = counts.total as f64 + k * vocabulary.types.max(1) as f64;
+
+    Ok(vocabulary
+        .words
+        .iter()
+        .map(|word| {
+            let count = seen.get(word.word.as_str()).copied().unwrap_or(0);
+            (word.word.clone(), (count as f64 + k) / denominator)
+        })
+        .collect())
+}
+
+async fn kneser_ney<S: BigramStore + ?Sized>(
+    store: &S,
+    context: &[&str],
+    second: Option<&str>,
+    groups: &[String],
+    discount: f64,
+) -> Result<HashMap<String, f64>, StoreError> {
+    // The lowest order uses continuation counts (distinct preceding words) and is itself
+    // interpolated with the uniform distribution so every vocabulary word keeps some mass.
+    let vocabulary = vocabulary(store, second, groups).await?;
+    let lowest = store
+        .find_counts(Context::Extending(""), second, groups)
+        .await?;
+    let continuations = lowest
+        .words
+        .iter()
+        .map(|word| (word.word.as_str(), word.contexts))
+        .collect::<HashMap<&str, u64>>();
+    let uniform = 1.0 / vocabulary.types.max(1) as f64;
+    let mut probabilities = vocabulary
+        .words
+        .iter()
+        .map(|word| {
+            let count = continuations.get(word.word.as_str()).copied().unwrap_or(0);
+            let probability = interpolate(count, &lowest, true, discount, uniform);
+            (word.word.clone(), probability)
+        })
+        .collect::<HashMap<String, f64>>();
+
+    for n in 1..=context.len() {
+        let first = context[context.len() - n..].join(" ");
+        // Only the highest order counts raw occurrences, lower orders count continuations.
+        let highest = n == context.len();
+        let context = if highest {
+            Context::Exact(&first)
+        } else {
+            Context::Extending(&first)
+        };
+        let counts = store.find_counts(context, second, groups).await?;
+
+        let seen = counts
+            .words
+            .iter()
+            .map(|word| (word.word.as_str(), word))
+            .collect::<HashMap<&str, _>>();
+        for (word, probability) in probabilities.iter_mut() {
+            let count =
+                seen.get(word.as_str()).map_or(
+                    0,
+                    |word| {
+                        if highest {
+                            word.count
+                        } else {
+                            word.contexts
+                        }
+                    },
+                );
+            *probability = interpolate(count, &counts, !highest, discount, *probability);
+        }
+    }
+
+    Ok(probabilities)
+}
+
+/// Discounts `count` out of the context's total and hands the freed mass to `lower`.
+fn interpolate(
+    count: u64,
+    counts: &ContextCounts,
+    continuation: bool,
+    discount: f64,
+    lower: f64,
+) -> f64 {
+    let total = if continuation {
+        counts.total_contexts
+    } else {
+        counts.total
+    };
+    if total == 0 {
+        return lower;
+    }
+
+    let total = total as f64;
+    (count as f64 - discount).max(0.0) / total + discount * counts.types as f64 / total * lower
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{config::Config, ingestion::NgramCounter, repositories::memory::MemoryBigramRepo};
+
+    async fn store() -> MemoryBigramRepo {
+        let store = MemoryBigramRepo::default();
+        let config = Config::default();
+        let mut counter = NgramCounter::new(&config, &config.languages[0]);
+        counter.add_text("the cat sat on the mat and the cat ran to the dog");
+        counter.flush(&store).await.unwrap();
+        store
+    }
+
+    #[actix_web::test]
+    async fn test_smoothed_probabilities_sum_to_one() {
+        let store = store().await;
+
+        for smoothing in [
+            Smoothing::AddK { k: 0.5 },
+            Smoothing::KneserNey { discount: 0.75 },
+        ] {
+            for context in [vec![], vec!["the"], vec!["on", "the"], vec!["a", "cat"]] {
+                let predictions = find_predictions(&store, &context, None, &[], &smoothing)
+                    .await
+                    .unwrap();
+                let sum = predictions.iter().map(|p| p.probability).sum::<f64>();
+                assert!(
+                    (sum - 1.0).abs() < 1e-9,
+                    "{smoothing:?} after {context:?} sums to {sum}"
+                );
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_smoothing_keeps_unseen_words() {
+        let store = store().await;
+
+        let smoothing = Smoothing::KneserNey { discount: 0.75 };
+        let predictions = find_predictions(&store, &["on", "the"], None, &[], &smoothing)
+            .await
+            .unwrap();
+
+        assert_eq!(predictions[0].word, "mat");
+        assert!(predictions.iter().all(|p| p.probability > 0.0));
+        // Nine words and the end of the sentence.
+        assert_eq!(predictions.len(), 10);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    models::{bigrams::Prediction, layouts::KeyPosition},
+    utils::get_key_group,
+};
+
+/// Standard deviation of touches around the center of the key meant, in key sizes.
+const TOUCH_SPREAD: f64 = 0.5;
+/// Furthest from the key meant a touch is taken to land, in key sizes.
+const REACH: f64 = 1.5;
+/// Points gesture and word paths are resampled to before they are compared.
+const PATH_POINTS: usize = 32;
+/// Standard deviation of the average distance of a gesture from the path of the word meant, in
+/// key sizes.
+const GESTURE_SPREAD: f64 = 0.5;
+
+/// A point on the layout, in the unit of its key positions.
+pub type Point = (f64, f64);
+
+fn distance(a: Point, b: Point) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+/// `PATH_POINTS` points spread evenly along `path`, so that paths drawn at different speeds
+/// can be compared point by point.
+fn resample(path: &[Point]) -> Vec<Point> {
+    let lengths = path
+        .windows(2)
+        .map(|segment| distance(segment[0], segment[1]))
+        .collect::<Vec<f64>>();
+    let total = lengths.iter().sum::<f64>();
+    if total == 0.0 {
+        return vec![path[0]; PATH_POINTS];
+    }
+
+    let mut points = vec![];
+    let (mut segment, mut start) = (0, 0.0);
+    for i in 0..PATH_POINTS {
+        let target = total * i as f64 / (PATH_POINTS - 1) as f64;
+        while segment + 1 < lengths.len() && start + lengths[segment] < target {
+            start += lengths[segment];
+            segment += 1;
+        }
+        let (a, b) = (path[segment], path[segment + 1]);
+        let along = if lengths[segment] > 0.0 {
+            ((target - start) / lengths[segment]).min(1.0)
+        } else {
+            0.0
+        };
+        points.push((a.0 + (b.0 - a.0) * along, a.1 + (b.1 - a.1) * along));
+    }
+    points
+}
+
+/// Noisy channel model of touch typing: a typed key may have been meant as any key near it, the
+/// more likely the closer it is.
+#[derive(Debug, Clone)]
+pub struct TouchModel {
+    positions: BTreeMap<String, KeyPosition>,
+    /// Keys that one press stands for anyway, such as those of a reduced keyboard.
+    groups: Vec<String>,
+}
+
+impl TouchModel {
+    pub fn new(positions: BTreeMap<String, KeyPosition>, groups: Vec<String>) -> Self {
+        Self { positions, groups }
+    }
+
+    fn position(&self, letter: char) -> Option<&KeyPosition> {
+        self.positions.get(letter.encode_utf8(&mut [0; 4]) as &str)
+    }
+
+    /// Distance between the centers of the key pressed and the key meant, in sizes of the
+    /// key meant.
+    fn key_distance(&self, typed: char, meant: char) -> Option<f64> {
+        let (typed, meant) = (self.position(typed)?, self.position(meant)?);
+        let x = (typed.x - meant.x) / meant.width;
+        let y = (typed.y - meant.y) / meant.height;
+        Some((x * x + y * y).sqrt())
+    }
+
+    /// Relative likelihood of pressing `typed` when meaning `meant`, 1 for the key itself.
+    pub fn likelihood(&self, typed: char, meant: char) -> f64 {
+        let grouped = get_key_group(typed, &self.groups).is_some_and(|group| group.contains(meant));
+        if typed == meant || grouped {
+            return 1.0;
+        }
+        match self.key_distance(typed, meant) {
+            Some(distance) if distance <= REACH => {
+                (-distance * distance / (2.0 * TOUCH_SPREAD * TOUCH_SPREAD)).exp()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Ambiguity groups that let every letter of `typed` stand for the keys within reach. Each
+    /// group is led by the letter it is for, which `get_key_group` looks groups up by first.
+    pub fn near_groups(&self, typed: &str) -> Vec<String> {
+        let mut groups = Vec::<String>::new();
+        for letter in typed.chars() {
+            if groups.iter().any(|group| group.starts_with(letter)) {
+                continue;
+            }
+            let near = self
+                .positions
+                .keys()
+                .flat_map(|key| key.parse::<char>().ok())
+                .filter(|key| *key != letter && self.likelihood(letter, *key) > 0.0);
+            groups.push([letter].into_iter().chain(near).collect());
+        }
+        groups
+    }
+
+    /// Weighs the probability of every prediction by how likely its first letters were to be
+    /// typed as `typed`, most likely first.
+    pub fn rescore(&self, predictions: Vec<Prediction>, typed: &str) -> Vec<Prediction> {
+        reweigh(predictions, |word| {
+            typed
+                .chars()
+                .zip(word.chars())
+                .map(|(typed, meant)| self.likelihood(typed, meant))
+                .product()
+        })
+    }
+
+    /// Keys whose center is within reach of `point`, nearest first.
+    pub fn keys_near(&self, point: Point) -> Vec<char> {
+        let mut keys = self
+            .positions
+            .iter()
+            .flat_map(|(key, position)| {
+                let x = (point.0 - position.x) / position.width;
+                let y = (point.1 - position.y) / position.height;
+                let distance = (x * x + y * y).sqrt();
+                Some((key.parse::<char>().ok()?, distance)).filter(|_| distance <= REACH)
+            })
+            .collect::<Vec<(char, f64)>>();
+        keys.sort_by(|a, b| a.1.total_cmp(&b.1));
+        keys.into_iter().map(|(key, _)| key).collect()
+    }
+
+    /// The path a finger would ideally draw to swipe `word`, through the centers of its keys.
+    fn word_path(&self, word: &str) -> Option<Vec<Point>> {
+        let mut path = word
+            .chars()
+            .map(|letter| self.position(letter).map(|key| (key.x, key.y)))
+            .collect::<Option<Vec<Point>>>()?;
+        // Double letters are one key the finger stays on.
+        path.dedup();
+        Some(path)
+    }
+
+    /// Average size of the keys, the unit gesture distances are measured in.
+    fn key_size(&self) -> f64 {
+        let sizes = self
+            .positions
+            .values()
+            .map(|key| (key.width + key.height) / 2.0)
+            .collect::<Vec<f64>>();
+        sizes.iter().sum::<f64>() / sizes.len() as f64
+    }
+
+    /// Relative likelihood of drawing `gesture` when swiping `word`, from the average distance
+    /// between their resampled paths. Words with keys off the layout cannot be swiped.
+    pub fn gesture_likelihood(&self, gesture: &[Point], word: &str) -> f64 {
+        let Some(path) = self.word_path(word).filter(|path| !path.is_empty()) else {
+            return 0.0;
+        };
+        if gesture.is_empty() {
+            return 0.0;
+        }
+        let distance = resample(gesture)
+            .into_iter()
+            .zip(resample(&path))
+            .map(|(a, b)| distance(a, b))
+            .sum::<f64>()
+            / PATH_POINTS as f64
+            / self.key_size();
+        (-distance * distance / (2.0 * GESTURE_SPREAD * GESTURE_SPREAD)).exp()
+    }
+
+    /// Weighs the probability of every prediction by how likely swiping it was to draw
+    /// `gesture`, most likely first.
+    pub fn decode(&self, predictions: Vec<Prediction>, gesture: &[Point]) -> Vec<Prediction> {
+        reweigh(predictions, |word| self.gesture_likelihood(gesture, word))
+    }
+}
+
+/// Multiplies the probability of every prediction by the `likelihood` of its word, drops the
+/// impossible ones and normalizes the rest, most likely first.
+fn reweigh(predictions: Vec<Prediction>, likelihood: impl Fn(&str) -> f64) -> Vec<Prediction> {
+    let mut predictions = predictions
+        .into_iter()
+        .map(|prediction| Prediction {
+            probability: prediction.probability * likelihood(&prediction.word),
+            ..prediction
+        })
+        .filter(|prediction| prediction.probability > 0.0)
+        .collect::<Vec<Prediction>>();
+
+    let total = predictions
+        .iter()
+        .map(|prediction| prediction.probability)
+        .sum::<f64>();
+    for prediction in predictions.iter_mut() {
+        prediction.probability /= total;
+    }
+    predictions.sort_by(|a, b| {
+        b.probability
+            .total_cmp(&a.probability)
+            .then_with(|| a.word.cmp(&b.word))
+    });
+    predictions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        models::{bigrams::PredictionKind, layouts::fixtures::touch_qwerty},
+        utils::matches_prefix,
+    };
+
+    fn qwerty() -> TouchModel {
+        touch_qwerty().touch_model().unwrap()
+    }
+
+    #[test]
+    fn test_likelihood() {
+        let touch = qwerty();
+
+        assert_eq!(touch.likelihood('e', 'e'), 1.0);
+        assert!(touch.likelihood('r', 'e') > touch.likelihood('r', 'd'));
+        assert!(touch.likelihood('r', 'd') > 0.0);
+        assert_eq!(touch.likelihood('r', 'p'), 0.0);
+        assert_eq!(touch.likelihood('1', 'e'), 0.0);
+    }
+
+    #[test]
+    fn test_near_groups() {
+        let touch = qwerty();
+
+        let groups = touch.near_groups("hrllo");
+        assert!(matches_prefix("hello", "hrllo", &groups));
+        assert!(matches_prefix("jello", "hrllo", &groups));
+        assert!(!matches_prefix("hallo", "hrllo", &groups));
+    }
+
+    #[test]
+    fn test_resample() {
+        let points = resample(&[(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (1.0, 3.0)]);
+        assert_eq!(points.len(), PATH_POINTS);
+        assert_eq!(points[0], (0.0, 0.0));
+        assert_eq!(points[PATH_POINTS - 1], (1.0, 3.0));
+        let step = distance(points[0], points[1]);
+        assert!((step - 4.0 / (PATH_POINTS - 1) as f64).abs() < 1e-9);
+
+        assert_eq!(resample(&[(2.0, 1.0)]), vec![(2.0, 1.0); PATH_POINTS]);
+    }
+
+    #[test]
+    fn test_gesture_likelihood() {
+        let touch = qwerty();
+        let key = |letter: char| {
+            let position = touch.position(letter).unwrap();
+            (position.x, position.y)
+        };
+
+        // A sloppy swipe through h, e, l and o, overshooting a little.
+        let gesture = [
+            key('h'),
+            (4.8, 0.2),
+            key('e'),
+            (6.0, 0.6),
+            key('l'),
+            key('o'),
+        ]
+        .map(|(x, y)| (x + 0.2, y - 0.1));
+        let hello = touch.gesture_likelihood(&gesture, "hello");
+        assert!(hello > touch.gesture_likelihood(&gesture, "help"));
+        assert!(hello > touch.gesture_likelihood(&gesture, "halo"));
+        assert!(hello > touch.gesture_likelihood(&gesture, "hole"));
+        assert_eq!(touch.gesture_likelihood(&gesture, "héllo"), 0.0);
+
+        assert_eq!(touch.keys_near(key('h'))[0], 'h');
+    }
+
+    #[test]
+    fn test_rescore() {
+        let touch = qwerty();
+        let predictions = [("jello", 0.6), ("hello", 0.4)]
+            .into_iter()
+            .map(|(word, probability)| Prediction {
+                word: word.to_string(),
+                probability,
+                kind: PredictionKind::Completion,
+            })
+            .collect();
+
+        let predictions = touch.rescore(predictions, "hrllo");
+        assert_eq!(predictions[0].word, "hello");
+        let total = predictions.iter().map(|p| p.probability).sum::<f64>();
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+}
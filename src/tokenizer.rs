@@ -0,0 +1,265 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Splits text into words on Unicode word boundaries (UAX #29), with a few switches for the
+/// cases deployments disagree on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tokenizer {
+    /// Keeps "don't" as one word instead of "don" and "t".
+    pub apostrophes: bool,
+    /// Keeps "e-mail" as one word instead of "e" and "mail".
+    pub hyphens: bool,
+    /// Keeps words without letters, such as "42" or "3.14".
+    pub numbers: bool,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Self {
+        Self {
+            apostrophes: true,
+            hyphens: true,
+            numbers: true,
+        }
+    }
+}
+
+/// Context of the first word of every sentence.
+pub const SENTENCE_START: &str = "<s>";
+/// Word that follows the last word of every sentence.
+pub const SENTENCE_END: &str = "</s>";
+
+fn is_terminator(c: char) -> bool {
+    matches!(
+        c,
+        '.' | '!' | '?' | '…' | '。' | '！' | '？' | '‼' | '⁇' | '⁈' | '⁉'
+    )
+}
+
+fn is_apostrophe(c: char) -> bool {
+    matches!(c, '\'' | '’')
+}
+
+fn is_hyphen(c: char) -> bool {
+    matches!(c, '-' | '‐' | '‑')
+}
+
+impl Tokenizer {
+    /// Reads the `TOKENIZER_APOSTROPHES`, `TOKENIZER_HYPHENS` and `TOKENIZER_NUMBERS` switches.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let switch = |name: &str, default: bool| match std::env::var(name).as_deref() {
+            Ok("true") => true,
+            Ok("false") => false,
+            Ok(_) => panic!("{name} must be true or false"),
+            Err(_) => default,
+        };
+
+        Self {
+            apostrophes: switch("TOKENIZER_APOSTROPHES", default.apostrophes),
+            hyphens: switch("TOKENIZER_HYPHENS", default.hyphens),
+            numbers: switch("TOKENIZER_NUMBERS", default.numbers),
+        }
+    }
+
+    /// Words of `text` with their byte offsets, punctuation and spaces left out. Sentence-ending
+    /// punctuation shows up as a `SENTENCE_END` word, never twice in a row.
+    pub fn words<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        let mut words = Vec::<(usize, &str)>::new();
+
+        for (start, segment) in text.split_word_bound_indices() {
+            let pieces = if segment.chars().any(char::is_alphanumeric) {
+                self.split(segment)
+            } else {
+                match segment.find(is_terminator) {
+                    Some(offset) => vec![(offset, SENTENCE_END)],
+                    None => continue,
+                }
+            };
+
+            for (offset, word) in pieces {
+                let start = start + offset;
+                let last = words.last().copied();
+                let joined = last.is_some_and(|(previous, last)| {
+                    let gap = &text[(previous + last.len()).min(start)..start];
+                    last != SENTENCE_END
+                        && word != SENTENCE_END
+                        && self.hyphens
+                        && gap.chars().count() == 1
+                        && gap.chars().all(is_hyphen)
+                });
+                match (last, words.last_mut()) {
+                    (_, Some((previous, last))) if joined => {
+                        *last = &text[*previous..start + word.len()]
+                    }
+                    (Some((_, SENTENCE_END)), _) if word == SENTENCE_END => {}
+                    _ => words.push((start, word)),
+                }
+            }
+        }
+
+        words.retain(|(_, word)| {
+            self.numbers || *word == SENTENCE_END || word.chars().any(char::is_alphabetic)
+        });
+        words
+    }
+
+    /// Splits text being typed into the sentence so far, starting with `SENTENCE_START`, and the
+    /// word still being typed, if the text ends inside one. A trailing apostrophe or hyphen
+    /// belongs to the unfinished word.
+    pub fn split_typed<'a>(&self, text: &'a str) -> (Vec<&'a str>, Option<&'a str>) {
+        let mut words = self.words(text);
+
+        let unfinished = match words.last() {
+            Some((_, SENTENCE_END)) => false,
+            Some((start, last)) => {
+                let mut rest = text[start + last.len()..].chars();
+                match (rest.next(), rest.next()) {
+                    (None, _) => true,
+                    (Some(c), None) => {
+                        (self.apostrophes && is_apostrophe(c)) || (self.hyphens && is_hyphen(c))
+                    }
+                    _ => false,
+                }
+            }
+            None => false,
+        };
+        let typed = if unfinished {
+            words.pop().map(|(start, _)| &text[start..])
+        } else {
+            None
+        };
+
+        let sentence = words
+            .iter()
+            .rposition(|(_, word)| *word == SENTENCE_END)
+            .map_or(0, |end| end + 1);
+        let context = [SENTENCE_START]
+            .into_iter()
+            .chain(words[sentence..].iter().map(|(_, word)| *word))
+            .collect();
+
+        (context, typed)
+    }
+
+    /// Splits a word segment at the punctuation UAX #29 lets through, such as the full stop of
+    /// "end.Start", keeping decimal separators and, if enabled, apostrophes.
+    fn split<'a>(&self, segment: &'a str) -> Vec<(usize, &'a str)> {
+        let chars = segment.char_indices().collect::<Vec<(usize, char)>>();
+        let mut pieces = vec![];
+        let mut start = 0;
+
+        for (i, (offset, c)) in chars.iter().enumerate() {
+            let numeric = |j: Option<usize>| {
+                j.and_then(|j| chars.get(j))
+                    .is_some_and(|(_, c)| c.is_numeric())
+            };
+            let kept = if is_apostrophe(*c) {
+                self.apostrophes
+            } else if matches!(c, '.' | ',') {
+                numeric(i.checked_sub(1)) && numeric(Some(i + 1))
+            } else {
+                !(c.is_ascii_punctuation() || *c == '·')
+            };
+
+            if !kept {
+                if start < *offset {
+                    pieces.push((start, &segment[start..*offset]));
+                }
+                if is_terminator(*c) {
+                    pieces.push((*offset, SENTENCE_END));
+                }
+                start = offset + c.len_utf8();
+            }
+        }
+        if start < segment.len() {
+            pieces.push((start, &segment[start..]));
+        }
+        pieces
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(tokenizer: &Tokenizer, text: &str) -> Vec<String> {
+        tokenizer
+            .words(text)
+            .into_iter()
+            .map(|(_, word)| word.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_words() {
+        let tokenizer = Tokenizer::default();
+
+        assert_eq!(
+            words(&tokenizer, "Don't send an e-mail, it's 3.14 o’clock."),
+            [
+                "Don't",
+                "send",
+                "an",
+                "e-mail",
+                "it's",
+                "3.14",
+                "o’clock",
+                "</s>"
+            ]
+        );
+        assert_eq!(
+            words(&tokenizer, "the end.Start again"),
+            ["the", "end", "</s>", "Start", "again"]
+        );
+        assert_eq!(
+            words(&tokenizer, "Really?! Yes... - maybe"),
+            ["Really", "</s>", "Yes", "</s>", "maybe"]
+        );
+        assert_eq!(words(&tokenizer, "well - maybe"), ["well", "maybe"]);
+        assert_eq!(
+            words(&tokenizer, "El niño, 'ya' comió"),
+            ["El", "niño", "ya", "comió"]
+        );
+        assert_eq!(
+            words(&tokenizer, "日本語のテキスト"),
+            ["日", "本", "語", "の", "テキスト"]
+        );
+    }
+
+    #[test]
+    fn test_split_typed() {
+        let tokenizer = Tokenizer::default();
+
+        assert_eq!(
+            tokenizer.split_typed("I don'"),
+            (vec!["<s>", "I"], Some("don'"))
+        );
+        assert_eq!(
+            tokenizer.split_typed("an e-"),
+            (vec!["<s>", "an"], Some("e-"))
+        );
+        assert_eq!(
+            tokenizer.split_typed("the cat "),
+            (vec!["<s>", "the", "cat"], None)
+        );
+        assert_eq!(
+            tokenizer.split_typed("The end. The c"),
+            (vec!["<s>", "The"], Some("c"))
+        );
+        assert_eq!(tokenizer.split_typed("the cat."), (vec!["<s>"], None));
+        assert_eq!(tokenizer.split_typed(""), (vec!["<s>"], None));
+    }
+
+    #[test]
+    fn test_switches() {
+        let tokenizer = Tokenizer {
+            apostrophes: false,
+            hyphens: false,
+            numbers: false,
+        };
+
+        assert_eq!(
+            words(&tokenizer, "Don't send an e-mail at 3.14"),
+            ["Don", "t", "send", "an", "e", "mail", "at"]
+        );
+    }
+}
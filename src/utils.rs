@@ -1,32 +1,191 @@
-pub fn get_regex(text: &str, keys: Vec<String>) -> String {
+use std::collections::BTreeMap;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Keys of a layout row. A key is one grapheme, which may be several chars, like "ё" written
+/// with a combining diaeresis.
+pub fn row_keys(row: &str) -> Vec<&str> {
+    row.graphemes(true).collect()
+}
+
+/// Ambiguity groups of the reduced 3x10 keyboard layouts without explicit groups describe:
+/// every column is a group, except that columns 3 and 4, and 5 and 6, share one. Any other
+/// geometry has no shared keys.
+pub fn legacy_groups(keys: &[String]) -> Vec<String> {
+    let rows = keys
+        .iter()
+        .map(|row| row_keys(row))
+        .collect::<Vec<Vec<&str>>>();
+    if rows.len() != 3 || rows.iter().any(|row| row.len() != 10) {
+        return vec![];
+    }
+
+    [0..1, 1..2, 2..3, 3..5, 5..7, 7..8, 8..9, 9..10]
+        .into_iter()
+        .map(|columns| {
+            rows.iter()
+                .flat_map(|row| &row[columns.clone()])
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+/// Letters of the digits of phone keypads (ITU E.161).
+pub fn keypad_letters() -> BTreeMap<String, String> {
+    [
+        ("2", "abc"),
+        ("3", "def"),
+        ("4", "ghi"),
+        ("5", "jkl"),
+        ("6", "mno"),
+        ("7", "pqrs"),
+        ("8", "tuv"),
+        ("9", "wxyz"),
+    ]
+    .into_iter()
+    .map(|(key, letters)| (key.to_string(), letters.to_string()))
+    .collect()
+}
+
+/// Spells a keypad key sequence as letters that `matches_prefix` and `get_regex` can match: every
+/// key becomes the first of its letters, and its letters become a group.
+pub fn spell_keys(
+    sequence: &str,
+    letters: &BTreeMap<String, String>,
+) -> Result<(String, Vec<String>), String> {
+    let mut spelled = String::new();
+    let mut groups = vec![];
+    for key in row_keys(sequence) {
+        let group = letters.get(key).filter(|group| !group.is_empty());
+        let group = group.ok_or_else(|| format!("Key {key:?} types no letters"))?;
+        spelled.extend(group.chars().next());
+        if !groups.contains(group) {
+            groups.push(group.clone());
+        }
+    }
+    Ok((spelled, groups))
+}
+
+/// The ambiguity group `letter` belongs to, if any. Where groups overlap, the one `letter`
+/// leads wins.
+pub fn get_key_group(letter: char, groups: &[String]) -> Option<String> {
+    let is_letter = |key: &str| key.chars().eq([letter]);
+    groups
+        .iter()
+        .find(|group| group.graphemes(true).next().is_some_and(is_letter))
+        .or_else(|| {
+            groups
+                .iter()
+                .find(|group| group.graphemes(true).any(is_letter))
+        })
+        .cloned()
+}
+
+pub fn get_regex(text: &str, groups: Vec<String>) -> String {
     text.chars()
-        .map(|letter| {
-            let col = keys.iter().find_map(|row| row.find(letter));
-
-            match col {
-                Some(3 | 4) => {
-                    let chars = keys
-                        .iter()
-                        .map(|row| row.chars().skip(3).take(2).collect::<String>())
-                        .collect::<String>();
-                    format!("[{}]", chars)
-                }
-                Some(5 | 6) => {
-                    let chars = keys
-                        .iter()
-                        .map(|row| row.chars().skip(5).take(2).collect::<String>())
-                        .collect::<String>();
-                    format!("[{}]", chars)
-                }
-                Some(c) => {
-                    let chars = keys
-                        .iter()
-                        .map(|row| row.chars().nth(c).unwrap().to_string())
-                        .collect::<String>();
-                    format!("[{}]", chars)
-                }
-                None => letter.to_string(),
-            }
+        .map(|letter| match get_key_group(letter, &groups) {
+            Some(chars) => format!("[{}]", escape_class(&chars)),
+            None => escape_regex(&letter.to_string()),
         })
         .collect::<String>()
 }
+
+/// Escapes the characters that are special inside a regex character class.
+fn escape_class(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | ']' | '[' | '^' | '-' => vec!['\\', c],
+            _ => vec![c],
+        })
+        .collect()
+}
+
+pub fn escape_regex(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| match c {
+            '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => {
+                vec!['\\', c]
+            }
+            _ => vec![c],
+        })
+        .collect()
+}
+
+/// In-memory equivalent of matching `word` against `^{get_regex(text, groups)}`.
+pub fn matches_prefix(word: &str, text: &str, groups: &[String]) -> bool {
+    let mut word = word.chars();
+
+    text.chars().all(|letter| match word.next() {
+        Some(c) => match get_key_group(letter, groups) {
+            Some(chars) => chars.contains(c),
+            None => c == letter,
+        },
+        None => false,
+    })
+}
+
+/// Whether `word` has at least two letters and none of them is lowercase, like "NASA".
+pub fn is_all_caps(word: &str) -> bool {
+    word.chars().filter(|c| c.is_alphabetic()).count() >= 2 && !word.chars().any(char::is_lowercase)
+}
+
+/// Whether only the first letter of `word` is uppercase, like "Paris".
+pub fn is_capitalized(word: &str) -> bool {
+    let mut chars = word.chars();
+    chars.next().is_some_and(char::is_uppercase) && !chars.any(char::is_uppercase)
+}
+
+pub fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// `count` as the `u32` n-gram counts are kept in, capped at its largest value rather than
+/// wrapped around.
+pub fn saturating_u32(count: u64) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_groups() {
+        let qwerty = legacy_groups(&["qwertyuiop", "asdfghjkl;", "zxcvbnm,./"].map(String::from));
+        assert_eq!(get_key_group('f', &qwerty).as_deref(), Some("rtfgvb"));
+        assert_eq!(get_key_group('q', &qwerty).as_deref(), Some("qaz"));
+        assert_eq!(get_key_group('1', &qwerty), None);
+
+        // Columns count keys, not bytes.
+        let greek = legacy_groups(&[";ςερτυθιοπ", "ασδφγηξκλ΄", "ζχψωβνμ,./"].map(String::from));
+        assert_eq!(get_key_group('α', &greek).as_deref(), Some(";αζ"));
+        assert_eq!(get_key_group('ρ', &greek).as_deref(), Some("ρτφγωβ"));
+        assert_eq!(get_key_group('π', &greek).as_deref(), Some("π΄/"));
+
+        assert!(legacy_groups(&["qwertyuiop".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_spell_keys() {
+        let (spelled, groups) = spell_keys("4663", &keypad_letters()).unwrap();
+        assert_eq!(spelled, "gmmd");
+        assert_eq!(groups, ["ghi", "mno", "def"]);
+        for word in ["good", "home", "gone", "hood", "goodbye"] {
+            assert!(matches_prefix(word, &spelled, &groups));
+        }
+        assert!(!matches_prefix("hoop", &spelled, &groups));
+
+        assert!(spell_keys("41", &keypad_letters()).is_err());
+    }
+
+    #[test]
+    fn test_get_regex() {
+        let groups = ["abc", "]-^"].map(String::from);
+        assert_eq!(get_regex("a.d-", groups.to_vec()), "[abc]\\.d[\\]\\-\\^]");
+    }
+}